@@ -0,0 +1,301 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt::Write;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use axum::extract::{Query, State};
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use utoipa::{IntoParams, ToSchema};
+use crate::metrics::{describe, escape};
+use crate::structures::{AppState, ResponseData};
+
+// default values
+const REQUEST_LOG_SIZE: usize = 1000;
+const VIEWER_WINDOW: u64 = 30;
+
+#[derive(Clone, Copy, PartialEq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum HlsRequestKind {
+    Playlist,
+    Segment
+}
+
+impl HlsRequestKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            HlsRequestKind::Playlist => "playlist",
+            HlsRequestKind::Segment => "segment",
+        }
+    }
+}
+
+#[derive(Clone, Serialize, ToSchema)]
+pub struct HlsRequest {
+    pub stream: String,
+    /// variant directory such as `720p`, or `master`
+    pub rendition: String,
+    pub kind: HlsRequestKind,
+    pub status: u16,
+    pub bytes: u64,
+    pub client: String,
+    pub session: String,
+    pub timestamp: u64
+}
+
+struct Session {
+    stream: String,
+    last_poll: Instant
+}
+
+#[derive(Default)]
+struct Usage {
+    owner: Option<String>,
+    bytes: u64,
+    playlist_requests: u64,
+    segment_requests: u64,
+    renditions: BTreeMap<String, u64>,
+    peak_viewers: usize
+}
+
+#[derive(Default)]
+struct AccountingState {
+    requests: VecDeque<HlsRequest>,
+    sessions: HashMap<String, Session>,
+    usage: HashMap<String, Usage>
+}
+
+impl AccountingState {
+    // sessions that polled a playlist recently enough to still be watching
+    fn viewers(&self, stream: &str) -> usize {
+        let window = Duration::from_secs(VIEWER_WINDOW);
+        self.sessions.values().filter(|s| s.stream == stream && s.last_poll.elapsed() < window).count()
+    }
+}
+
+// per-stream HLS delivery totals, kept after a stream is deleted so its traffic can still be billed
+pub struct HlsAccounting {
+    state: Mutex<AccountingState>
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+// signed URLs carry a token per playback, otherwise a client is its address and user agent
+pub fn session_id(stream: &str, token: Option<&str>, client: IpAddr, user_agent: &str) -> String {
+    let source = match token {
+        Some(token) => format!("{}|{}", stream, token),
+        None => format!("{}|{}|{}", stream, client, user_agent),
+    };
+
+    hex::encode(&Sha256::digest(source.as_bytes())[..8])
+}
+
+pub struct Delivery<'a> {
+    pub stream: &'a str,
+    pub owner: &'a str,
+    pub rendition: &'a str,
+    pub kind: HlsRequestKind,
+    pub status: u16,
+    pub bytes: u64,
+    pub client: IpAddr,
+    pub session: String
+}
+
+impl HlsAccounting {
+    pub fn new() -> Self {
+        HlsAccounting {
+            state: Mutex::new(AccountingState::default())
+        }
+    }
+
+    pub fn record(&self, delivery: Delivery) {
+        let mut state = match self.state.lock() {
+            Ok(state) => state,
+            Err(_) => return,
+        };
+
+        let window = Duration::from_secs(VIEWER_WINDOW);
+        state.sessions.retain(|_, session| session.last_poll.elapsed() < window);
+
+        if delivery.kind == HlsRequestKind::Playlist && delivery.status == 200 {
+            state.sessions.insert(delivery.session.clone(), Session { stream: delivery.stream.to_string(), last_poll: Instant::now() });
+        }
+
+        let viewers = state.viewers(delivery.stream);
+        let usage = state.usage.entry(delivery.stream.to_string()).or_default();
+
+        usage.owner = Some(delivery.owner.to_string());
+
+        if delivery.status == 200 {
+            usage.bytes += delivery.bytes;
+            *usage.renditions.entry(delivery.rendition.to_string()).or_insert(0) += delivery.bytes;
+
+            match delivery.kind {
+                HlsRequestKind::Playlist => usage.playlist_requests += 1,
+                HlsRequestKind::Segment => usage.segment_requests += 1,
+            }
+        }
+
+        usage.peak_viewers = usage.peak_viewers.max(viewers);
+
+        if state.requests.len() >= REQUEST_LOG_SIZE {
+            state.requests.pop_front();
+        }
+
+        state.requests.push_back(HlsRequest {
+            stream: delivery.stream.to_string(),
+            rendition: delivery.rendition.to_string(),
+            kind: delivery.kind,
+            status: delivery.status,
+            bytes: delivery.bytes,
+            client: delivery.client.to_string(),
+            session: delivery.session,
+            timestamp: now()
+        });
+    }
+
+    pub fn viewers(&self, stream: &str) -> usize {
+        self.state.lock().map(|state| state.viewers(stream)).unwrap_or(0)
+    }
+
+    fn report(&self) -> UsageReport {
+        let state = match self.state.lock() {
+            Ok(state) => state,
+            Err(_) => return UsageReport { streams: vec![], owners: vec![] },
+        };
+
+        let mut streams: Vec<StreamUsage> = state.usage.iter().map(|(stream, usage)| StreamUsage {
+            stream: stream.clone(),
+            owner: usage.owner.clone(),
+            bytes: usage.bytes,
+            playlist_requests: usage.playlist_requests,
+            segment_requests: usage.segment_requests,
+            renditions: usage.renditions.clone(),
+            viewers: state.viewers(stream),
+            peak_viewers: usage.peak_viewers
+        }).collect();
+        streams.sort_by(|a, b| a.stream.cmp(&b.stream));
+
+        let mut owners: BTreeMap<String, OwnerUsage> = BTreeMap::new();
+
+        for usage in &streams {
+            let owner = usage.owner.clone().unwrap_or_default();
+            let entry = owners.entry(owner.clone()).or_insert_with(|| OwnerUsage { owner, bytes: 0, streams: 0 });
+            entry.bytes += usage.bytes;
+            entry.streams += 1;
+        }
+
+        UsageReport { streams, owners: owners.into_values().collect() }
+    }
+
+    pub fn render(&self, out: &mut String) {
+        let state = match self.state.lock() {
+            Ok(state) => state,
+            Err(_) => return,
+        };
+
+        let mut bytes = String::new();
+        let mut requests = String::new();
+        let mut viewers = String::new();
+
+        for (stream, usage) in state.usage.iter() {
+            let labels = format!("stream=\"{}\"", escape(stream));
+
+            for (rendition, sent) in &usage.renditions {
+                let _ = writeln!(bytes, "rtsp_hls_bytes_total{{{},rendition=\"{}\"}} {}", labels, escape(rendition), sent);
+            }
+
+            for (kind, count) in [(HlsRequestKind::Playlist, usage.playlist_requests), (HlsRequestKind::Segment, usage.segment_requests)] {
+                let _ = writeln!(requests, "rtsp_hls_requests_total{{{},kind=\"{}\"}} {}", labels, kind.as_str(), count);
+            }
+
+            let _ = writeln!(viewers, "rtsp_hls_viewers{{{}}} {}", labels, state.viewers(stream));
+        }
+
+        describe(out, "rtsp_hls_bytes_total", "counter", "Bytes of HLS playlists and segments delivered.");
+        out.push_str(&bytes);
+        describe(out, "rtsp_hls_requests_total", "counter", "HLS playlist and segment requests served.");
+        out.push_str(&requests);
+        describe(out, "rtsp_hls_viewers", "gauge", "HLS sessions that polled a playlist in the last 30 seconds.");
+        out.push_str(&viewers);
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct StreamUsage {
+    pub stream: String,
+    /// API key that created the stream
+    pub owner: Option<String>,
+    pub bytes: u64,
+    pub playlist_requests: u64,
+    pub segment_requests: u64,
+    /// bytes per rendition
+    pub renditions: BTreeMap<String, u64>,
+    pub viewers: usize,
+    pub peak_viewers: usize
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct OwnerUsage {
+    pub owner: String,
+    pub bytes: u64,
+    pub streams: usize
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct UsageReport {
+    pub streams: Vec<StreamUsage>,
+    pub owners: Vec<OwnerUsage>
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/usage",
+    tag = "monitoring",
+    responses(
+        (status = 200, description = "HLS bytes, requests and viewers per stream and per owning API key", body = ResponseData<UsageReport>)
+    )
+)]
+pub async fn get_usage(State(state): State<AppState>) -> Json<ResponseData<UsageReport>> {
+    Json(ResponseData {
+        status: true,
+        message: String::from("Fetch Success"),
+        data: Some(state.hls.report())
+    })
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct RequestsQuery {
+    pub stream: Option<String>,
+    pub session: Option<String>
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/usage/requests",
+    tag = "monitoring",
+    params(RequestsQuery),
+    responses(
+        (status = 200, description = "Most recent HLS requests, oldest first", body = ResponseData<Vec<HlsRequest>>)
+    )
+)]
+pub async fn get_requests(Query(query): Query<RequestsQuery>, State(state): State<AppState>) -> Json<ResponseData<Vec<HlsRequest>>> {
+    let requests = state.hls.state.lock()
+        .map(|state| state.requests
+            .iter()
+            .filter(|r| query.stream.as_ref().map(|id| id == &r.stream).unwrap_or(true))
+            .filter(|r| query.session.as_ref().map(|id| id == &r.session).unwrap_or(true))
+            .cloned()
+            .collect())
+        .unwrap_or_default();
+
+    Json(ResponseData {
+        status: true,
+        message: String::from("Fetch Success"),
+        data: Some(requests)
+    })
+}
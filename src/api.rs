@@ -0,0 +1,193 @@
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::{Extension, Json};
+use serde::Serialize;
+use crate::auth::Principal;
+use crate::selector::{with_total, StreamQuery};
+use crate::stream_manager::{find_stream, pause_ingest, reconfigure_stream, reload_stream, resume_ingest, start_stream, stop_stream, stream_info};
+use crate::openapi::ErrorResponse;
+use crate::structures::{AppState, ResponseData, RtspStream, StreamData, StreamInfo, StreamPatch};
+
+// every /api/v1 response is a ResponseData envelope, with the HTTP status matching `status`
+pub fn respond<T: Serialize>(status: StatusCode, message: impl Into<String>, data: Option<T>) -> Response {
+    (status, Json(ResponseData {
+        status: status.is_success(),
+        message: message.into(),
+        data
+    })).into_response()
+}
+
+/// Create a stream and start its pipeline.
+#[utoipa::path(
+    post,
+    path = "/api/v1/streams",
+    tag = "streams",
+    request_body = StreamData,
+    responses(
+        (status = 201, description = "Stream created and starting", body = ResponseData<StreamInfo>),
+        (status = 409, description = "A stream for this source and type already exists", body = ErrorResponse),
+        (status = 422, description = "The source URL was rejected", body = ErrorResponse),
+        (status = 429, description = "Rate limit or per-key quota exceeded", body = ErrorResponse),
+        (status = 503, description = "Server stream or transcode capacity reached", body = ErrorResponse)
+    )
+)]
+pub async fn create_stream(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    Json(payload): Json<StreamData>,
+) -> Response {
+    match start_stream(&state, &principal.name, payload, false).await {
+        Ok(stream) => respond(StatusCode::CREATED, "Initiated", Some(stream_info(&state, &stream))),
+        Err(response) => response,
+    }
+}
+
+/// List streams, optionally filtered by label selector, group, state and type, sorted and paginated.
+#[utoipa::path(
+    get,
+    path = "/api/v1/streams",
+    tag = "streams",
+    params(StreamQuery),
+    responses(
+        (status = 200, description = "Matching streams, with the match count in `X-Total-Count`", body = ResponseData<Vec<StreamInfo>>),
+        (status = 400, description = "Invalid selector, sort or order", body = ErrorResponse)
+    )
+)]
+pub async fn list_streams(Query(query): Query<StreamQuery>, State(state): State<AppState>) -> Response {
+    match select_streams(&state, &query).await {
+        Ok((streams, total)) => {
+            let streams: Vec<StreamInfo> = streams.iter().map(|stream| stream_info(&state, stream)).collect();
+            with_total(respond(StatusCode::OK, "Fetch Success", Some(streams)), total)
+        }
+        Err(response) => response,
+    }
+}
+
+// the filtered, sorted page of streams behind list_streams and the legacy /getStreams, with the total before paging
+pub async fn select_streams(state: &AppState, query: &StreamQuery) -> Result<(Vec<RtspStream>, usize), Response> {
+    let streams_lock = state.streams.lock().await;
+
+    let selected = query.select(streams_lock.values()).map_err(|e| respond::<()>(StatusCode::BAD_REQUEST, e, None))?;
+    let total = selected.len();
+
+    Ok((query.page(selected).into_iter().cloned().collect(), total))
+}
+
+/// Get a stream's details and live stats.
+#[utoipa::path(
+    get,
+    path = "/api/v1/streams/{id}",
+    tag = "streams",
+    params(("id" = String, Path, description = "Stream ID")),
+    responses(
+        (status = 200, description = "Stream details and live stats", body = ResponseData<StreamInfo>),
+        (status = 404, description = "Stream not found", body = ErrorResponse)
+    )
+)]
+pub async fn show_stream(Path(id): Path<String>, State(state): State<AppState>) -> Response {
+    match find_stream(&state, &id).await {
+        Some(stream) => respond(StatusCode::OK, "Fetch Success", Some(stream_info(&state, &stream))),
+        None => respond::<()>(StatusCode::NOT_FOUND, "Stream not found", None),
+    }
+}
+
+/// Change a stream's encoding or HLS options. HLS options apply in place; an encoding change rebuilds
+/// the pipeline, continuing the HLS playlists after an `EXT-X-DISCONTINUITY`.
+#[utoipa::path(
+    patch,
+    path = "/api/v1/streams/{id}",
+    tag = "streams",
+    params(("id" = String, Path, description = "Stream ID")),
+    request_body = StreamPatch,
+    responses(
+        (status = 200, description = "Options applied to the running pipeline", body = ResponseData<StreamInfo>),
+        (status = 202, description = "Pipeline is being rebuilt with the new options", body = ResponseData<StreamInfo>),
+        (status = 404, description = "Stream not found", body = ErrorResponse),
+        (status = 503, description = "Server transcode capacity reached", body = ErrorResponse)
+    )
+)]
+pub async fn update_stream(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    Json(patch): Json<StreamPatch>,
+) -> Response {
+    match reconfigure_stream(&state, &id, patch).await {
+        Ok((stream, true)) => respond(StatusCode::ACCEPTED, "Rebuilding", Some(stream_info(&state, &stream))),
+        Ok((stream, false)) => respond(StatusCode::OK, "Updated", Some(stream_info(&state, &stream))),
+        Err(response) => response,
+    }
+}
+
+/// Stop and remove a stream.
+#[utoipa::path(
+    delete,
+    path = "/api/v1/streams/{id}",
+    tag = "streams",
+    params(("id" = String, Path, description = "Stream ID")),
+    responses(
+        (status = 200, description = "Stream stopped and removed", body = ResponseData<StreamInfo>),
+        (status = 404, description = "Stream not found", body = ErrorResponse)
+    )
+)]
+pub async fn destroy_stream(Path(id): Path<String>, State(state): State<AppState>) -> Response {
+    match stop_stream(&state, &id).await {
+        Some(stream) => respond(StatusCode::OK, "Deleted", Some(stream_info(&state, &stream))),
+        None => respond::<()>(StatusCode::NOT_FOUND, "Stream not found", None),
+    }
+}
+
+/// Rebuild a stream's pipeline.
+#[utoipa::path(
+    post,
+    path = "/api/v1/streams/{id}/restart",
+    tag = "streams",
+    params(("id" = String, Path, description = "Stream ID")),
+    responses(
+        (status = 202, description = "Pipeline is being rebuilt", body = ResponseData<StreamInfo>),
+        (status = 404, description = "Stream not found", body = ErrorResponse)
+    )
+)]
+pub async fn restart_stream(Path(id): Path<String>, State(state): State<AppState>) -> Response {
+    match reload_stream(&state, &id).await {
+        Ok(stream) => respond(StatusCode::ACCEPTED, "Restarting", Some(stream_info(&state, &stream))),
+        Err(response) => response,
+    }
+}
+
+/// Pause ingest, keeping the stream's configuration and pipeline.
+#[utoipa::path(
+    post,
+    path = "/api/v1/streams/{id}/pause",
+    tag = "streams",
+    params(("id" = String, Path, description = "Stream ID")),
+    responses(
+        (status = 200, description = "Ingest paused", body = ResponseData<StreamInfo>),
+        (status = 404, description = "Stream not found", body = ErrorResponse),
+        (status = 409, description = "Stream is stopped or failed", body = ErrorResponse)
+    )
+)]
+pub async fn pause_stream(Path(id): Path<String>, State(state): State<AppState>) -> Response {
+    match pause_ingest(&state, &id).await {
+        Ok(stream) => respond(StatusCode::OK, "Paused", Some(stream_info(&state, &stream))),
+        Err(response) => response,
+    }
+}
+
+/// Resume a paused stream.
+#[utoipa::path(
+    post,
+    path = "/api/v1/streams/{id}/resume",
+    tag = "streams",
+    params(("id" = String, Path, description = "Stream ID")),
+    responses(
+        (status = 200, description = "Ingest resumed", body = ResponseData<StreamInfo>),
+        (status = 404, description = "Stream not found", body = ErrorResponse)
+    )
+)]
+pub async fn resume_stream(Path(id): Path<String>, State(state): State<AppState>) -> Response {
+    match resume_ingest(&state, &id).await {
+        Ok(stream) => respond(StatusCode::OK, "Resumed", Some(stream_info(&state, &stream))),
+        Err(response) => response,
+    }
+}
@@ -0,0 +1,199 @@
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use axum::extract::{Request, State};
+use axum::http::{header, HeaderMap, HeaderName, HeaderValue, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
+use crate::config::{Config, CorsConfig, Role};
+use crate::structures::ResponseData;
+
+// default values
+const EVENTS_TOKEN_TTL: u64 = 60;
+
+#[derive(Clone, Debug)]
+pub struct Principal {
+    pub name: String,
+    pub role: Role
+}
+
+fn presented_key(headers: &HeaderMap) -> Option<String> {
+    if let Some(value) = headers.get(header::AUTHORIZATION).and_then(|v| v.to_str().ok()) {
+        if let Some(token) = value.strip_prefix("Bearer ") {
+            return Some(token.trim().to_string());
+        }
+    }
+
+    headers.get("x-api-key").and_then(|v| v.to_str().ok()).map(|v| v.trim().to_string())
+}
+
+// compares digests so the check does not short-circuit on the first differing byte of the key
+fn key_matches(presented: &str, expected: &str) -> bool {
+    Sha256::digest(presented.as_bytes()) == Sha256::digest(expected.as_bytes())
+}
+
+pub fn authenticate(config: &Config, key: Option<&str>) -> Option<Principal> {
+    if !config.auth.enabled {
+        return Some(Principal { name: String::from("anonymous"), role: Role::Admin });
+    }
+
+    let key = key?;
+
+    config.auth.keys
+        .iter()
+        .find(|k| key_matches(key, &k.key))
+        .map(|k| Principal { name: k.name.clone(), role: k.role })
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+fn events_signature(key: &str, name: &str, expires: u64) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(format!("events:{}:{}", name, expires).as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+// `<name>.<expiry>.<signature>`, signed with the principal's own API key so removing the key revokes its tokens
+pub fn events_token(config: &Config, principal: &Principal) -> Option<(String, u64)> {
+    let key = config.auth.keys.iter().find(|k| k.name == principal.name)?;
+    let expires = now() + EVENTS_TOKEN_TTL;
+
+    Some((format!("{}.{}.{}", key.name, expires, events_signature(&key.key, &key.name, expires)), expires))
+}
+
+fn verify_events_token(config: &Config, token: &str) -> Option<Principal> {
+    let mut parts = token.rsplitn(3, '.');
+    let (signature, expires, name) = (parts.next()?, parts.next()?, parts.next()?);
+    let expires = expires.parse::<u64>().ok().filter(|expires| *expires >= now())?;
+
+    config.auth.keys
+        .iter()
+        .find(|k| k.name == name && key_matches(signature, &events_signature(&k.key, &k.name, expires)))
+        .map(|k| Principal { name: k.name.clone(), role: k.role })
+}
+
+// EventSource can't send headers, so `/events` also takes a short-lived `?token=` from `/events/token`
+pub async fn authorize_query_token(State(config): State<Arc<Config>>, mut request: Request, next: Next) -> Response {
+    let token = request.uri().query()
+        .and_then(|query| url::form_urlencoded::parse(query.as_bytes()).find(|(name, _)| name == "token"))
+        .map(|(_, token)| token.into_owned());
+
+    if let Some(principal) = token.and_then(|token| verify_events_token(&config, &token)) {
+        request.extensions_mut().insert(principal);
+    }
+
+    next.run(request).await
+}
+
+pub fn reject(status: StatusCode, message: &str) -> Response {
+    (status, Json(ResponseData::<()> {
+        status: false,
+        message: message.to_string(),
+        data: None
+    })).into_response()
+}
+
+// `known` is a principal the listener already established, such as a verified client certificate
+pub fn check_access(config: &Config, known: Option<&Principal>, headers: &HeaderMap, role: Role) -> Result<Principal, StatusCode> {
+    let principal = match known {
+        Some(principal) => Some(principal.clone()),
+        None => authenticate(config, presented_key(headers).as_deref()),
+    };
+
+    let principal = match principal {
+        Some(principal) => principal,
+        None => return Err(StatusCode::UNAUTHORIZED),
+    };
+
+    if principal.role < role {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    Ok(principal)
+}
+
+pub async fn authorize(State((config, role)): State<(Arc<Config>, Role)>, mut request: Request, next: Next) -> Response {
+    let principal = match check_access(&config, request.extensions().get::<Principal>(), request.headers(), role) {
+        Ok(principal) => principal,
+        Err(status) => return reject(status, status.canonical_reason().unwrap_or_default()),
+    };
+
+    request.extensions_mut().insert(principal);
+
+    next.run(request).await
+}
+
+pub fn cors_layer(config: &CorsConfig) -> CorsLayer {
+    if config.allowed_origins.is_empty() || config.allowed_origins.iter().any(|o| o == "*") {
+        return CorsLayer::new()
+            .allow_origin(Any)
+            .allow_methods(Any)
+            .allow_headers(Any);
+    }
+
+    let origins: Vec<HeaderValue> = config.allowed_origins
+        .iter()
+        .filter_map(|origin| HeaderValue::from_str(origin).ok())
+        .collect();
+
+    CorsLayer::new()
+        .allow_origin(AllowOrigin::list(origins))
+        .allow_methods([Method::GET, Method::POST, Method::PATCH, Method::DELETE, Method::OPTIONS])
+        .allow_headers([header::AUTHORIZATION, header::CONTENT_TYPE, HeaderName::from_static("x-api-key")])
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::ApiKey;
+    use super::*;
+
+    fn config() -> Config {
+        let mut config = Config::default();
+        config.auth.enabled = true;
+        config.auth.keys = vec![ApiKey { name: String::from("control.room"), key: String::from("change-me"), role: Role::Viewer }];
+        config
+    }
+
+    #[test]
+    fn events_token_round_trips_with_the_key_role() {
+        let config = config();
+        let principal = authenticate(&config, Some("change-me")).unwrap();
+        let (token, _) = events_token(&config, &principal).unwrap();
+
+        let verified = verify_events_token(&config, &token).unwrap();
+        assert_eq!(verified.name, "control.room");
+        assert_eq!(verified.role, Role::Viewer);
+    }
+
+    #[test]
+    fn events_token_rejects_tampering_expiry_and_removed_keys() {
+        let config = config();
+        let signature = events_signature("change-me", "control.room", now() + 60);
+
+        assert!(verify_events_token(&config, &format!("control.room.{}.{}", now() + 60, signature)).is_some());
+        assert!(verify_events_token(&config, &format!("control.room.{}.{}", now() + 3600, signature)).is_none());
+
+        let expired = now() - 1;
+        assert!(verify_events_token(&config, &format!("control.room.{}.{}", expired, events_signature("change-me", "control.room", expired))).is_none());
+
+        let (token, _) = events_token(&config, &Principal { name: String::from("control.room"), role: Role::Viewer }).unwrap();
+        assert!(verify_events_token(&Config::default(), &token).is_none());
+    }
+
+    #[test]
+    fn check_access_needs_a_key_with_the_role() {
+        let config = config();
+        let mut headers = HeaderMap::new();
+
+        assert_eq!(check_access(&config, None, &headers, Role::Viewer).err(), Some(StatusCode::UNAUTHORIZED));
+
+        headers.insert("x-api-key", HeaderValue::from_static("change-me"));
+        assert_eq!(check_access(&config, None, &headers, Role::Viewer).map(|p| p.name).ok().as_deref(), Some("control.room"));
+        assert_eq!(check_access(&config, None, &headers, Role::Operator).err(), Some(StatusCode::FORBIDDEN));
+    }
+}
@@ -0,0 +1,504 @@
+use std::collections::{BTreeMap, HashSet};
+use std::time::Duration;
+use axum::body::Bytes;
+use axum::extract::{Query, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Extension;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use tokio::time::Instant;
+use utoipa::{IntoParams, ToSchema};
+use crate::api::respond;
+use crate::auth::Principal;
+use crate::config::Role;
+use crate::openapi::ErrorResponse;
+use crate::selector::{validate_metadata, StreamQuery};
+use crate::stream_manager::{redact_rtsp, reload_stream, start_stream, stop_stream, stream_id};
+use crate::structures::{AppState, EncodeOptions, HLSOptions, ResponseData, StreamData, StreamEvent, StreamEventKind, StreamState, validate_video_bitrate, WebRtcOptions};
+use crate::validation::validate_source;
+
+// default values
+const IMPORT_CONCURRENCY: usize = 4;
+const MAX_IMPORT_CONCURRENCY: usize = 32;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Format {
+    Json,
+    Yaml,
+    Csv
+}
+
+impl Format {
+    pub fn parse(name: &str) -> Option<Format> {
+        match name.to_ascii_lowercase().as_str() {
+            "json" => Some(Format::Json),
+            "yaml" | "yml" => Some(Format::Yaml),
+            "csv" => Some(Format::Csv),
+            _ => None,
+        }
+    }
+
+    fn from_content_type(content_type: &str) -> Option<Format> {
+        let mime = content_type.split(';').next().unwrap_or_default().trim();
+
+        match mime {
+            "application/json" => Some(Format::Json),
+            "application/yaml" | "application/x-yaml" | "text/yaml" => Some(Format::Yaml),
+            "text/csv" => Some(Format::Csv),
+            _ => None,
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Format::Json => "application/json",
+            Format::Yaml => "application/yaml",
+            Format::Csv => "text/csv",
+        }
+    }
+}
+
+// CSV can't nest, so HLS options are flattened into their own columns
+#[derive(Deserialize, Serialize)]
+struct CsvRecord {
+    rtsp: String,
+    stream_type: String,
+    encode_options: Option<EncodeOptions>,
+    hls_max_files: Option<u32>,
+    hls_duration: Option<u32>,
+    video_bitrate: Option<u32>,
+    priority: Option<i32>,
+    name: Option<String>,
+    group: Option<String>,
+    // `key=value` pairs separated by `;`
+    labels: Option<String>,
+    on_demand: Option<bool>,
+    max_viewers: Option<u32>,
+    // JSON, too nested for columns of its own
+    webrtc_options: Option<String>
+}
+
+fn parse_labels(labels: &str) -> Result<BTreeMap<String, String>, String> {
+    labels.split(';')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.split_once('=') {
+            Some((key, value)) => Ok((key.trim().to_string(), value.trim().to_string())),
+            None => Err(format!("Invalid label '{}', expected key=value", pair)),
+        })
+        .collect()
+}
+
+fn format_labels(labels: &BTreeMap<String, String>) -> String {
+    labels.iter().map(|(key, value)| format!("{}={}", key, value)).collect::<Vec<String>>().join(";")
+}
+
+impl TryFrom<CsvRecord> for StreamData {
+    type Error = String;
+
+    fn try_from(record: CsvRecord) -> Result<Self, String> {
+        let hls_options = match (record.hls_max_files, record.hls_duration) {
+            (Some(max_files), Some(duration)) => Some(HLSOptions { max_files, duration }),
+            (None, None) => None,
+            _ => return Err(String::from("hls_max_files and hls_duration must be given together")),
+        };
+
+        Ok(StreamData {
+            rtsp: record.rtsp,
+            stream_type: record.stream_type,
+            encode_options: record.encode_options,
+            hls_options,
+            video_bitrate: record.video_bitrate,
+            priority: record.priority,
+            name: record.name.filter(|name| !name.is_empty()),
+            group: record.group.filter(|group| !group.is_empty()),
+            labels: record.labels.as_deref().map(parse_labels).transpose()?,
+            on_demand: record.on_demand,
+            max_viewers: record.max_viewers,
+            webrtc_options: record.webrtc_options
+                .filter(|options| !options.is_empty())
+                .map(|options| serde_json::from_str(&options).map_err(|e| format!("Invalid webrtc_options: {}", e)))
+                .transpose()?
+        })
+    }
+}
+
+impl From<&StreamData> for CsvRecord {
+    fn from(stream: &StreamData) -> Self {
+        CsvRecord {
+            rtsp: stream.rtsp.clone(),
+            stream_type: stream.stream_type.clone(),
+            encode_options: stream.encode_options.clone(),
+            hls_max_files: stream.hls_options.as_ref().map(|o| o.max_files),
+            hls_duration: stream.hls_options.as_ref().map(|o| o.duration),
+            video_bitrate: stream.video_bitrate,
+            priority: stream.priority,
+            name: stream.name.clone(),
+            group: stream.group.clone(),
+            labels: stream.labels.as_ref().map(format_labels),
+            on_demand: stream.on_demand,
+            max_viewers: stream.max_viewers,
+            webrtc_options: stream.webrtc_options.as_ref().and_then(|options| serde_json::to_string(options).ok())
+        }
+    }
+}
+
+pub fn parse_streams(format: Format, body: &[u8]) -> Result<Vec<StreamData>, String> {
+    match format {
+        Format::Json => serde_json::from_slice(body).map_err(|e| format!("Invalid JSON: {}", e)),
+        Format::Yaml => serde_yaml::from_slice(body).map_err(|e| format!("Invalid YAML: {}", e)),
+        Format::Csv => {
+            let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(body);
+
+            reader.deserialize::<CsvRecord>()
+                .enumerate()
+                .map(|(row, record)| record.map_err(|e| e.to_string()).and_then(StreamData::try_from).map_err(|e| format!("Invalid CSV row {}: {}", row + 1, e)))
+                .collect()
+        }
+    }
+}
+
+pub fn render_streams(format: Format, streams: &[StreamData]) -> Result<String, String> {
+    match format {
+        Format::Json => serde_json::to_string_pretty(streams).map_err(|e| e.to_string()),
+        Format::Yaml => serde_yaml::to_string(streams).map_err(|e| e.to_string()),
+        Format::Csv => {
+            let mut writer = csv::Writer::from_writer(vec![]);
+
+            for stream in streams {
+                writer.serialize(CsvRecord::from(stream)).map_err(|e| e.to_string())?;
+            }
+
+            let bytes = writer.into_inner().map_err(|e| e.to_string())?;
+            String::from_utf8(bytes).map_err(|e| e.to_string())
+        }
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct BulkQuery {
+    /// json, yaml or csv; defaults to the Content-Type on import and JSON on export
+    pub format: Option<String>,
+    /// how many streams are started at once; each one holds its slot until it is running, failed or idle
+    pub concurrency: Option<usize>,
+    /// export source URLs with their username and password; admin keys only
+    pub include_credentials: Option<bool>
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ImportItem {
+    pub index: usize,
+    pub source: String,
+    pub stream_type: String,
+    pub id: Option<String>,
+    pub status: bool,
+    pub message: String
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ImportReport {
+    pub total: usize,
+    pub started: usize,
+    pub failed: usize,
+    pub items: Vec<ImportItem>
+}
+
+fn report(items: Vec<ImportItem>) -> ImportReport {
+    let started = items.iter().filter(|item| item.status).count();
+
+    ImportReport {
+        total: items.len(),
+        started,
+        failed: items.len() - started,
+        items
+    }
+}
+
+// pulls the message out of a ResponseData error envelope
+async fn rejection_message(response: Response) -> String {
+    let status = response.status();
+
+    axum::body::to_bytes(response.into_body(), 64 * 1024).await.ok()
+        .and_then(|body| serde_json::from_slice::<serde_json::Value>(&body).ok())
+        .and_then(|value| value.get("message").and_then(|m| m.as_str()).map(String::from))
+        .unwrap_or_else(|| format!("Rejected with {}", status))
+}
+
+// every definition is checked before anything starts, so a bad file changes nothing
+async fn validate_all(state: &AppState, streams: &[StreamData]) -> Vec<Option<String>> {
+    let existing: HashSet<String> = state.streams.lock().await.values().map(|s| s.id.clone()).collect();
+    let mut seen = HashSet::new();
+    let mut errors = vec![];
+
+    for stream in streams {
+        let id = stream_id(&stream.rtsp, &stream.stream_type);
+
+        let error = if stream.stream_type.is_empty() {
+            Some(String::from("stream_type is required"))
+        } else if let Err(e) = validate_metadata(stream.name.as_ref(), stream.group.as_ref(), stream.labels.as_ref()) {
+            Some(e)
+        } else if let Some(Err(e)) = stream.webrtc_options.as_ref().map(|options| options.validate_with(&state.config.ice)) {
+            Some(e)
+        } else if let Err(e) = validate_video_bitrate(&stream.stream_type, stream.encode_options.as_ref().unwrap_or(&EncodeOptions::NONE), stream.video_bitrate) {
+            Some(e)
+        } else if !seen.insert(id.clone()) {
+            Some(String::from("Duplicate of an earlier entry"))
+        } else if existing.contains(&id) {
+            Some(format!("Stream '{}' already exists", id))
+        } else {
+            validate_source(&state.config.sources, &stream.rtsp).await.err()
+        };
+
+        errors.push(error);
+    }
+
+    errors
+}
+
+// waits for a started stream to leave the queue and the startup phase, so `concurrency` bounds real startups;
+// a queued stream waits as long as it has to, a starting one at most `startup.start_timeout`
+async fn settled(state: &AppState, mut events: broadcast::Receiver<StreamEvent>, id: &str) -> (bool, String) {
+    let timeout = Duration::from_secs(state.config.startup.start_timeout);
+    let mut deadline = None;
+
+    loop {
+        let next = match deadline {
+            Some(deadline) => match tokio::time::timeout_at(deadline, events.recv()).await {
+                Ok(next) => next,
+                Err(_) => return (true, String::from("Still starting")),
+            },
+            None => events.recv().await,
+        };
+
+        let kind = match next {
+            Ok(event) if event.stream == id => event.kind,
+            Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => return (true, String::from("Initiated")),
+        };
+
+        match kind {
+            StreamEventKind::StateChanged { state: StreamState::Queued, .. } => deadline = None,
+            StreamEventKind::StateChanged { state: StreamState::Starting, .. } => deadline = Some(Instant::now() + timeout),
+            StreamEventKind::StateChanged { state: StreamState::Running, .. } => return (true, String::from("Running")),
+            StreamEventKind::StateChanged { state: StreamState::Paused, .. } => return (true, String::from("Paused")),
+            StreamEventKind::StateChanged { state: StreamState::Idle, .. } => return (true, String::from("Idle until a viewer connects")),
+            StreamEventKind::StateChanged { state: StreamState::Failed, message } => return (false, message),
+            StreamEventKind::StateChanged { state: StreamState::Stopped, .. } | StreamEventKind::Deleted => return (false, String::from("Stopped before it started")),
+            _ => {}
+        }
+    }
+}
+
+// definitions are all validated before any starts, then started with bounded concurrency
+#[utoipa::path(
+    post,
+    path = "/api/v1/streams/import",
+    tag = "streams",
+    params(BulkQuery),
+    request_body(content = Vec<StreamData>, description = "Stream definitions as JSON, YAML or CSV"),
+    responses(
+        (status = 200, description = "Per-item result of starting every stream", body = ResponseData<ImportReport>),
+        (status = 400, description = "The file could not be parsed", body = ErrorResponse),
+        (status = 422, description = "Some definitions are invalid, nothing was started", body = ResponseData<ImportReport>)
+    )
+)]
+pub async fn import_streams(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    Query(query): Query<BulkQuery>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let format = query.format.as_deref().and_then(Format::parse)
+        .or_else(|| headers.get(header::CONTENT_TYPE).and_then(|v| v.to_str().ok()).and_then(Format::from_content_type))
+        .unwrap_or(Format::Json);
+
+    let streams = match parse_streams(format, &body) {
+        Ok(streams) => streams,
+        Err(e) => return respond::<()>(StatusCode::BAD_REQUEST, e, None),
+    };
+
+    let errors = validate_all(&state, &streams).await;
+
+    if errors.iter().any(Option::is_some) {
+        let items = streams.iter().zip(errors).enumerate().map(|(index, (stream, error))| ImportItem {
+            index,
+            source: redact_rtsp(&stream.rtsp),
+            stream_type: stream.stream_type.clone(),
+            id: Some(stream_id(&stream.rtsp, &stream.stream_type)),
+            status: false,
+            message: error.unwrap_or_else(|| String::from("Not started, other entries are invalid")),
+        }).collect();
+
+        return respond(StatusCode::UNPROCESSABLE_ENTITY, "Validation failed", Some(report(items)));
+    }
+
+    let concurrency = query.concurrency.unwrap_or(IMPORT_CONCURRENCY).clamp(1, MAX_IMPORT_CONCURRENCY);
+
+    let mut items: Vec<ImportItem> = futures::stream::iter(streams.into_iter().enumerate())
+        .map(|(index, stream)| {
+            let state = state.clone();
+            let owner = principal.name.clone();
+
+            async move {
+                let source = redact_rtsp(&stream.rtsp);
+                let stream_type = stream.stream_type.clone();
+
+                // subscribe before starting so the first state change can't be missed
+                let events = state.events.subscribe();
+
+                match start_stream(&state, &owner, stream, false).await {
+                    Ok(started) => {
+                        let (status, message) = settled(&state, events, &started.id).await;
+                        ImportItem { index, source, stream_type, id: Some(started.id), status, message }
+                    }
+                    Err(response) => ImportItem { index, source, stream_type, id: None, status: false, message: rejection_message(response).await },
+                }
+            }
+        })
+        .buffer_unordered(concurrency)
+        .collect()
+        .await;
+
+    items.sort_by_key(|item| item.index);
+
+    respond(StatusCode::OK, "Import complete", Some(report(items)))
+}
+
+// written in a format `import` accepts, so an export can be replayed on another instance
+#[utoipa::path(
+    get,
+    path = "/api/v1/streams/export",
+    tag = "streams",
+    params(BulkQuery),
+    responses(
+        (status = 200, description = "Stream definitions in the requested format, with source passwords masked unless `include_credentials` is set", body = Vec<StreamData>),
+        (status = 400, description = "Unknown format", body = ErrorResponse),
+        (status = 403, description = "Credentials were requested without an admin key", body = ErrorResponse)
+    )
+)]
+pub async fn export_streams(State(state): State<AppState>, Extension(principal): Extension<Principal>, Query(query): Query<BulkQuery>) -> Response {
+    let format = match query.format.as_deref() {
+        Some(name) => match Format::parse(name) {
+            Some(format) => format,
+            None => return respond::<()>(StatusCode::BAD_REQUEST, format!("Unknown format '{}'", name), None),
+        },
+        None => Format::Json,
+    };
+
+    // operators can list the cameras, but only admins get the passwords along with them
+    let include_credentials = query.include_credentials.unwrap_or(false);
+
+    if include_credentials && principal.role < Role::Admin {
+        return respond::<()>(StatusCode::FORBIDDEN, "Exporting credentials needs an admin key", None);
+    }
+
+    let streams_lock = state.streams.lock().await;
+    let mut streams: Vec<StreamData> = streams_lock.values().map(|s| StreamData {
+        rtsp: if include_credentials { s.rtsp.clone() } else { redact_rtsp(&s.rtsp) },
+        stream_type: s.stream_type.clone(),
+        encode_options: Some(s.encode_options.clone()),
+        hls_options: s.hls_options.clone(),
+        video_bitrate: s.video_bitrate,
+        priority: Some(s.priority),
+        name: s.name.clone(),
+        group: s.group.clone(),
+        labels: if s.labels.is_empty() { None } else { Some(s.labels.clone()) },
+        on_demand: Some(s.on_demand),
+        max_viewers: s.max_viewers,
+        webrtc_options: if s.webrtc_options == WebRtcOptions::default() { None } else { Some(s.webrtc_options.clone()) }
+    }).collect();
+    drop(streams_lock);
+
+    streams.sort_by(|a, b| (&a.rtsp, &a.stream_type).cmp(&(&b.rtsp, &b.stream_type)));
+
+    match render_streams(format, &streams) {
+        Ok(body) => ([(header::CONTENT_TYPE, format.content_type())], body).into_response(),
+        Err(e) => respond::<()>(StatusCode::INTERNAL_SERVER_ERROR, e, None),
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ActionItem {
+    pub id: String,
+    pub status: bool,
+    pub message: String
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ActionReport {
+    pub matched: usize,
+    pub items: Vec<ActionItem>
+}
+
+// ids of the streams a bulk action applies to; an empty filter would match everything, so it is refused
+async fn matching_streams(state: &AppState, query: &StreamQuery) -> Result<Vec<String>, Response> {
+    if !query.has_filter() {
+        return Err(respond::<()>(StatusCode::BAD_REQUEST, "A selector, group, state or stream_type filter is required", None));
+    }
+
+    let streams_lock = state.streams.lock().await;
+
+    query.select(streams_lock.values())
+        .map(|selected| selected.into_iter().map(|s| s.id.clone()).collect())
+        .map_err(|e| respond::<()>(StatusCode::BAD_REQUEST, e, None))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/streams/restart",
+    tag = "streams",
+    params(StreamQuery),
+    responses(
+        (status = 202, description = "Matching streams are being rebuilt", body = ResponseData<ActionReport>),
+        (status = 400, description = "Missing or invalid filter", body = ErrorResponse)
+    )
+)]
+pub async fn restart_matching(State(state): State<AppState>, Query(query): Query<StreamQuery>) -> Response {
+    let ids = match matching_streams(&state, &query).await {
+        Ok(ids) => ids,
+        Err(response) => return response,
+    };
+
+    let mut items = vec![];
+
+    for id in ids {
+        items.push(match reload_stream(&state, &id).await {
+            Ok(_) => ActionItem { id, status: true, message: String::from("Restarting") },
+            Err(response) => ActionItem { id, status: false, message: rejection_message(response).await },
+        });
+    }
+
+    respond(StatusCode::ACCEPTED, "Restarting", Some(ActionReport { matched: items.len(), items }))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/streams",
+    tag = "streams",
+    params(StreamQuery),
+    responses(
+        (status = 200, description = "Matching streams stopped and removed", body = ResponseData<ActionReport>),
+        (status = 400, description = "Missing or invalid filter", body = ErrorResponse)
+    )
+)]
+pub async fn delete_matching(State(state): State<AppState>, Query(query): Query<StreamQuery>) -> Response {
+    let ids = match matching_streams(&state, &query).await {
+        Ok(ids) => ids,
+        Err(response) => return response,
+    };
+
+    let mut items = vec![];
+
+    for id in ids {
+        items.push(match stop_stream(&state, &id).await {
+            Some(_) => ActionItem { id, status: true, message: String::from("Deleted") },
+            None => ActionItem { id, status: false, message: String::from("Stream not found") },
+        });
+    }
+
+    respond(StatusCode::OK, "Deleted", Some(ActionReport { matched: items.len(), items }))
+}
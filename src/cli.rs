@@ -0,0 +1,158 @@
+use std::fs;
+use std::path::Path;
+use crate::bulk::Format;
+
+const DEFAULT_SERVER: &str = "http://127.0.0.1:5005";
+
+const USAGE: &str = "usage:
+  rtsp import <file> [--server URL] [--key KEY] [--format json|yaml|csv] [--concurrency N]
+  rtsp export [--server URL] [--key KEY] [--format json|yaml|csv] [--output FILE] [--include-credentials]
+
+The API key can also be set with RTSP_API_KEY.";
+
+struct Options {
+    positional: Vec<String>,
+    server: String,
+    key: Option<String>,
+    format: Option<String>,
+    concurrency: Option<String>,
+    output: Option<String>,
+    include_credentials: bool
+}
+
+fn parse_options(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        positional: vec![],
+        server: DEFAULT_SERVER.to_string(),
+        key: std::env::var("RTSP_API_KEY").ok(),
+        format: None,
+        concurrency: None,
+        output: None,
+        include_credentials: false
+    };
+
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().cloned().ok_or_else(|| format!("{} needs a value\n\n{}", arg, USAGE));
+
+        match arg.as_str() {
+            "--server" => options.server = value()?,
+            "--key" => options.key = Some(value()?),
+            "--format" => options.format = Some(value()?),
+            "--concurrency" => options.concurrency = Some(value()?),
+            "--output" => options.output = Some(value()?),
+            "--include-credentials" => options.include_credentials = true,
+            _ if arg.starts_with("--") => return Err(format!("Unknown option {}\n\n{}", arg, USAGE)),
+            _ => options.positional.push(arg.clone()),
+        }
+    }
+
+    Ok(options)
+}
+
+fn request(client: &reqwest::Client, method: reqwest::Method, url: String, key: &Option<String>) -> reqwest::RequestBuilder {
+    let builder = client.request(method, url);
+
+    match key {
+        Some(key) => builder.bearer_auth(key),
+        None => builder,
+    }
+}
+
+async fn import(options: Options) -> Result<(), String> {
+    let file = options.positional.first().ok_or_else(|| USAGE.to_string())?;
+    let body = fs::read(file).map_err(|e| format!("Failed to read {}: {}", file, e))?;
+
+    let format = match &options.format {
+        Some(name) => Format::parse(name),
+        None => Path::new(file).extension().and_then(|e| e.to_str()).and_then(Format::parse),
+    }.unwrap_or(Format::Json);
+
+    let mut url = format!("{}/api/v1/streams/import", options.server.trim_end_matches('/'));
+
+    if let Some(concurrency) = &options.concurrency {
+        url.push_str(&format!("?concurrency={}", concurrency));
+    }
+
+    let client = reqwest::Client::new();
+    let response = request(&client, reqwest::Method::POST, url, &options.key)
+        .header(reqwest::header::CONTENT_TYPE, format.content_type())
+        .body(body)
+        .send()
+        .await
+        .map_err(|e| format!("Request failed: {}", e))?;
+
+    let status = response.status();
+    let report: serde_json::Value = response.json().await.map_err(|e| format!("Invalid response: {}", e))?;
+
+    if let Some(items) = report["data"]["items"].as_array() {
+        for item in items {
+            println!(
+                "{:>4}  {:<5}  {:<16}  {}  {}",
+                item["index"],
+                if item["status"].as_bool().unwrap_or(false) { "ok" } else { "error" },
+                item["id"].as_str().unwrap_or("-"),
+                item["source"].as_str().unwrap_or_default(),
+                item["message"].as_str().unwrap_or_default(),
+            );
+        }
+
+        println!(
+            "{} started, {} failed of {}",
+            report["data"]["started"], report["data"]["failed"], report["data"]["total"]
+        );
+    }
+
+    if status.is_success() {
+        Ok(())
+    } else {
+        Err(report["message"].as_str().map(String::from).unwrap_or_else(|| status.to_string()))
+    }
+}
+
+async fn export(options: Options) -> Result<(), String> {
+    let format = options.format.clone().unwrap_or_else(|| String::from("json"));
+    let mut url = format!("{}/api/v1/streams/export?format={}", options.server.trim_end_matches('/'), format);
+
+    if options.include_credentials {
+        url.push_str("&include_credentials=true");
+    }
+
+    let client = reqwest::Client::new();
+    let response = request(&client, reqwest::Method::GET, url, &options.key)
+        .send()
+        .await
+        .map_err(|e| format!("Request failed: {}", e))?;
+
+    let status = response.status();
+    let body = response.text().await.map_err(|e| format!("Invalid response: {}", e))?;
+
+    if !status.is_success() {
+        return Err(format!("{}: {}", status, body));
+    }
+
+    match &options.output {
+        Some(path) => fs::write(path, body).map_err(|e| format!("Failed to write {}: {}", path, e)),
+        None => {
+            print!("{}", body);
+            Ok(())
+        }
+    }
+}
+
+// handles `import` and `export`, returning None for anything else so the server starts as usual
+pub async fn run(args: &[String]) -> Option<Result<(), String>> {
+    let command = args.first()?;
+
+    let options = match parse_options(&args[1..]) {
+        Ok(options) => options,
+        Err(e) => return Some(Err(e)),
+    };
+
+    match command.as_str() {
+        "import" => Some(import(options).await),
+        "export" => Some(export(options).await),
+        _ => None,
+    }
+}
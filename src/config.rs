@@ -0,0 +1,322 @@
+use std::fs;
+use std::net::SocketAddr;
+use std::path::Path;
+use ipnet::IpNet;
+use serde::Deserialize;
+use crate::structures::IceOptions;
+
+const CONFIG_PATH: &str = "./config.json";
+
+#[derive(Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct Config {
+    pub mqtt: Option<MqttConfig>,
+    pub auth: AuthConfig,
+    pub cors: CorsConfig,
+    pub hls: HlsConfig,
+    pub signalling: SignallingConfig,
+    pub listen: ListenConfig,
+    pub tls: Option<TlsConfig>,
+    pub sources: SourcesConfig,
+    pub limits: LimitsConfig,
+    pub startup: StartupConfig,
+    pub on_demand: OnDemandConfig,
+    pub reconnect: ReconnectConfig,
+    // STUN/TURN servers and candidate restrictions for every WebRTC stream
+    pub ice: IceOptions
+}
+
+fn default_http_addr() -> String { String::from("127.0.0.1:5005") }
+fn default_signalling_addr() -> String { String::from("127.0.0.1:8443") }
+fn default_reload_interval() -> u64 { 60 }
+
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct ListenConfig {
+    #[serde(default = "default_http_addr")]
+    pub http: String,
+    #[serde(default = "default_signalling_addr")]
+    pub signalling: String
+}
+
+impl Default for ListenConfig {
+    fn default() -> Self {
+        ListenConfig {
+            http: default_http_addr(),
+            signalling: default_signalling_addr()
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct TlsConfig {
+    pub cert: String,
+    pub key: String,
+    pub signalling_addr: Option<String>,
+    pub client_ca: Option<String>,
+    pub admin_addr: Option<String>,
+    #[serde(default = "default_reload_interval")]
+    pub reload_interval: u64
+}
+
+fn default_parallelism() -> usize { 4 }
+fn default_per_host() -> usize { 2 }
+fn default_start_timeout() -> u64 { 15 }
+
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct StartupConfig {
+    // pipelines brought up at the same time
+    #[serde(default = "default_parallelism")]
+    pub parallelism: usize,
+    #[serde(default = "default_per_host")]
+    pub per_host: usize,
+    // seconds a start slot is held if no media arrives from the source
+    #[serde(default = "default_start_timeout")]
+    pub start_timeout: u64
+}
+
+impl Default for StartupConfig {
+    fn default() -> Self {
+        StartupConfig {
+            parallelism: default_parallelism(),
+            per_host: default_per_host(),
+            start_timeout: default_start_timeout()
+        }
+    }
+}
+
+fn default_idle_timeout() -> u64 { 60 }
+fn default_start_wait() -> u64 { 20 }
+
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct OnDemandConfig {
+    // seconds without viewers or playlist fetches before an on-demand pipeline is stopped
+    #[serde(default = "default_idle_timeout")]
+    pub idle_timeout: u64,
+    // seconds a playlist request waits for a woken stream's first segments
+    #[serde(default = "default_start_wait")]
+    pub start_wait: u64
+}
+
+impl Default for OnDemandConfig {
+    fn default() -> Self {
+        OnDemandConfig {
+            idle_timeout: default_idle_timeout(),
+            start_wait: default_start_wait()
+        }
+    }
+}
+
+fn default_reconnect_delay() -> u64 { 2 }
+fn default_max_reconnect_delay() -> u64 { 30 }
+fn default_stable_run() -> u64 { 60 }
+
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct ReconnectConfig {
+    // rebuild a failed pipeline instead of leaving the stream failed
+    pub enabled: bool,
+    // seconds before the first retry, doubled after each failure up to `max_delay`
+    #[serde(default = "default_reconnect_delay")]
+    pub delay: u64,
+    #[serde(default = "default_max_reconnect_delay")]
+    pub max_delay: u64,
+    // seconds a pipeline has to run before its next failure starts again from `delay`
+    #[serde(default = "default_stable_run")]
+    pub stable_run: u64
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        ReconnectConfig {
+            enabled: false,
+            delay: default_reconnect_delay(),
+            max_delay: default_max_reconnect_delay(),
+            stable_run: default_stable_run()
+        }
+    }
+}
+
+fn default_burst() -> u32 { 10 }
+
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct LimitsConfig {
+    pub max_streams: Option<usize>,
+    pub max_transcodes: Option<usize>,
+    pub per_key_max_streams: Option<usize>,
+    // requests per second on the mutation routes
+    pub rate: Option<f64>,
+    #[serde(default = "default_burst")]
+    pub burst: u32
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        LimitsConfig {
+            max_streams: None,
+            max_transcodes: None,
+            per_key_max_streams: None,
+            rate: None,
+            burst: default_burst()
+        }
+    }
+}
+
+fn default_allowed_schemes() -> Vec<String> { vec![String::from("rtsp"), String::from("rtsps")] }
+fn default_max_url_length() -> usize { 2048 }
+
+// link-local ranges cover cloud metadata endpoints
+fn default_deny_cidrs() -> Vec<IpNet> {
+    ["0.0.0.0/8", "127.0.0.0/8", "169.254.0.0/16", "::1/128", "::ffff:127.0.0.0/104", "fe80::/10", "::/128"]
+        .iter()
+        .filter_map(|net| net.parse().ok())
+        .collect()
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct SourcesConfig {
+    #[serde(default = "default_allowed_schemes")]
+    pub allowed_schemes: Vec<String>,
+    pub allow_cidrs: Vec<IpNet>,
+    #[serde(default = "default_deny_cidrs")]
+    pub deny_cidrs: Vec<IpNet>,
+    #[serde(default = "default_max_url_length")]
+    pub max_url_length: usize
+}
+
+impl Default for SourcesConfig {
+    fn default() -> Self {
+        SourcesConfig {
+            allowed_schemes: default_allowed_schemes(),
+            allow_cidrs: vec![],
+            deny_cidrs: default_deny_cidrs(),
+            max_url_length: default_max_url_length()
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Viewer,
+    Operator,
+    Admin
+}
+
+#[derive(Deserialize, Clone)]
+pub struct ApiKey {
+    pub name: String,
+    pub key: String,
+    pub role: Role
+}
+
+#[derive(Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct AuthConfig {
+    pub enabled: bool,
+    pub keys: Vec<ApiKey>
+}
+
+fn default_token_ttl() -> u64 { 3600 }
+
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct HlsConfig {
+    pub signing_secret: Option<String>,
+    #[serde(default = "default_token_ttl")]
+    pub token_ttl: u64,
+    pub bind_ip: bool
+}
+
+impl Default for HlsConfig {
+    fn default() -> Self {
+        HlsConfig {
+            signing_secret: None,
+            token_ttl: default_token_ttl(),
+            bind_ip: false
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct SignallingConfig {
+    pub secret: Option<String>,
+    #[serde(default = "default_token_ttl")]
+    pub token_ttl: u64
+}
+
+impl Default for SignallingConfig {
+    fn default() -> Self {
+        SignallingConfig {
+            secret: None,
+            token_ttl: default_token_ttl()
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct CorsConfig {
+    pub allowed_origins: Vec<String>
+}
+
+fn default_mqtt_port() -> u16 { 1883 }
+fn default_client_id() -> String { String::from("rtsptoweb-server") }
+fn default_state_topic() -> String { String::from("rtsp/streams/{id}/state") }
+fn default_event_topic() -> String { String::from("rtsp/streams/{id}/events") }
+fn default_health_topic() -> String { String::from("rtsp/streams/{id}/health") }
+fn default_server_topic() -> String { String::from("rtsp/server/status") }
+fn default_health_interval() -> u64 { 30 }
+
+#[derive(Deserialize, Clone)]
+pub struct MqttConfig {
+    pub host: String,
+    #[serde(default = "default_mqtt_port")]
+    pub port: u16,
+    #[serde(default = "default_client_id")]
+    pub client_id: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    #[serde(default = "default_state_topic")]
+    pub state_topic: String,
+    #[serde(default = "default_event_topic")]
+    pub event_topic: String,
+    #[serde(default = "default_health_topic")]
+    pub health_topic: String,
+    #[serde(default = "default_server_topic")]
+    pub server_topic: String,
+    #[serde(default = "default_health_interval")]
+    pub health_interval: u64
+}
+
+fn is_loopback(addr: &str) -> bool {
+    match addr.parse::<SocketAddr>() {
+        Ok(addr) => addr.ip().is_loopback(),
+        Err(_) => addr.rsplit_once(':').map(|(host, _)| host == "localhost").unwrap_or(false),
+    }
+}
+
+pub fn load_config() -> Result<Config, String> {
+    let path = std::env::var("RTSP_CONFIG").unwrap_or_else(|_| CONFIG_PATH.to_string());
+
+    if !Path::new(&path).exists() {
+        return Ok(Config::default());
+    }
+
+    let contents = fs::read_to_string(&path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    let config: Config = serde_json::from_str(&contents).map_err(|e| format!("Failed to parse {}: {}", path, e))?;
+
+    config.ice.validate().map_err(|e| format!("Invalid ice config in {}: {}", path, e))?;
+
+    // without keys every caller is an anonymous admin, which is only acceptable on this machine
+    if !config.auth.enabled && !is_loopback(&config.listen.http) {
+        return Err(format!("Refusing to serve the API on {} without auth, enable auth with keys in {} or listen on a loopback address", config.listen.http, path));
+    }
+
+    Ok(config)
+}
@@ -0,0 +1,97 @@
+use std::path::Path;
+use std::time::{Duration, Instant};
+use futures::channel::mpsc::UnboundedReceiver;
+use futures::StreamExt;
+use gstreamer::prelude::ElementExt;
+use gstreamer_app::gst;
+use crate::structures::{AppState, StreamState};
+
+// default values
+pub const IDLE_MESSAGE: &str = "rtsp-idle";
+const REAP_INTERVAL: u64 = 5;
+const SEGMENT_POLL: u64 = 200;
+
+// records viewer interest in a stream, waking it if it is idle; returns false for unknown streams
+pub async fn touch_stream(state: &AppState, id: &str) -> bool {
+    let mut streams_lock = state.streams.lock().await;
+
+    match streams_lock.values_mut().find(|s| s.id == id) {
+        Some(stream) => {
+            stream.last_demand = Some(Instant::now());
+
+            if stream.on_demand && stream.state == StreamState::Idle {
+                stream.wake.notify_waiters();
+            }
+            true
+        }
+        None => false,
+    }
+}
+
+// stream IDs requested by signalling consumers
+pub async fn run_demands(state: AppState, mut demands: UnboundedReceiver<String>) {
+    while let Some(id) = demands.next().await {
+        touch_stream(&state, &id).await;
+    }
+}
+
+// asks the supervisors of on-demand streams nobody has watched for `idle_timeout` to stop their pipelines
+pub async fn reap_idle(state: AppState) {
+    let idle_timeout = Duration::from_secs(state.config.on_demand.idle_timeout);
+    let mut ticker = tokio::time::interval(Duration::from_secs(REAP_INTERVAL));
+
+    loop {
+        ticker.tick().await;
+
+        let mut streams_lock = state.streams.lock().await;
+
+        for stream in streams_lock.values_mut() {
+            if !stream.on_demand || stream.paused {
+                continue;
+            }
+
+            let pipeline = match &stream.pipeline {
+                Some(pipeline) => pipeline,
+                None => continue,
+            };
+
+            // a connected WebRTC consumer counts as a viewer for as long as the session lasts
+            if stream.viewers.lock().map(|viewers| viewers.count() > 0).unwrap_or(false) {
+                stream.last_demand = Some(Instant::now());
+                continue;
+            }
+
+            let idle = stream.last_demand.map(|at| at.elapsed() >= idle_timeout).unwrap_or(true);
+
+            if idle {
+                if let Some(bus) = pipeline.bus() {
+                    let _ = bus.post(gst::message::Application::new(gst::Structure::new_empty(IDLE_MESSAGE)));
+                }
+            }
+        }
+    }
+}
+
+// holds a playlist request for an on-demand stream that was just woken until its first segments are written
+pub async fn wait_for_segments(state: &AppState, id: &str, path: &Path) -> bool {
+    let deadline = Instant::now() + Duration::from_secs(state.config.on_demand.start_wait);
+
+    loop {
+        let streams_lock = state.streams.lock().await;
+        let started = match streams_lock.values().find(|s| s.id == id) {
+            Some(stream) if stream.on_demand => stream.playlists.lock().map(|playlists| playlists.is_started()).unwrap_or(false),
+            _ => return true,
+        };
+        drop(streams_lock);
+
+        if started && path.exists() {
+            return true;
+        }
+
+        if Instant::now() >= deadline {
+            return false;
+        }
+
+        tokio::time::sleep(Duration::from_millis(SEGMENT_POLL)).await;
+    }
+}
@@ -0,0 +1,96 @@
+use std::convert::Infallible;
+use std::sync::Arc;
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::Response;
+use axum::Extension;
+use futures::Stream;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+use crate::api::respond;
+use crate::auth::{events_token, Principal};
+use crate::config::Config;
+use crate::openapi::ErrorResponse;
+use crate::structures::{ResponseData, StreamEvent};
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct EventsQuery {
+    pub stream: Option<String>,
+    /// token from `/events/token`, for clients that can't send an API key header
+    pub token: Option<String>
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct EventsToken {
+    pub token: String,
+    /// unix timestamp
+    pub expires: u64
+}
+
+// for a browser `EventSource`, which can't send the API key header
+#[utoipa::path(
+    get,
+    path = "/events/token",
+    tag = "monitoring",
+    responses(
+        (status = 200, description = "Short-lived token for `GET /events?token=`", body = ResponseData<EventsToken>),
+        (status = 400, description = "The caller did not authenticate with an API key", body = ErrorResponse)
+    )
+)]
+pub async fn get_events_token(State(config): State<Arc<Config>>, Extension(principal): Extension<Principal>) -> Response {
+    match events_token(&config, &principal) {
+        Some((token, expires)) => respond(StatusCode::OK, "Token issued", Some(EventsToken { token, expires })),
+        None => respond::<()>(StatusCode::BAD_REQUEST, "Tokens are only issued to API keys", None),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/events",
+    tag = "monitoring",
+    params(EventsQuery),
+    responses(
+        (status = 200, description = "Server-sent stream of lifecycle events", body = StreamEvent, content_type = "text/event-stream")
+    )
+)]
+pub async fn stream_events(
+    Query(query): Query<EventsQuery>,
+    State(events): State<broadcast::Sender<StreamEvent>>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let receiver = events.subscribe();
+    let filter = query.stream;
+
+    let stream = futures::stream::unfold(receiver, move |mut receiver| {
+        let filter = filter.clone();
+
+        async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(event) => {
+                        if filter.as_ref().map(|id| id != &event.stream).unwrap_or(false) {
+                            continue;
+                        }
+
+                        let sse = Event::default()
+                            .event(event.kind.name())
+                            .json_data(&event)
+                            .unwrap_or_else(|_| Event::default());
+
+                        return Some((Ok(sse), receiver));
+                    }
+                    Err(RecvError::Lagged(skipped)) => {
+                        let sse = Event::default().event("lagged").data(skipped.to_string());
+                        return Some((Ok(sse), receiver));
+                    }
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        }
+    });
+
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
@@ -0,0 +1,260 @@
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path as FsPath;
+use std::time::{SystemTime, UNIX_EPOCH};
+use axum::extract::{ConnectInfo, Path, Query, State};
+use axum::http::{header, HeaderMap, StatusCode, Uri};
+use axum::response::{IntoResponse, Response};
+use axum::{Extension, Json};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use sha2::Sha256;
+use crate::accounting::{session_id, Delivery, HlsRequestKind};
+use crate::auth::{check_access, reject, Principal};
+use crate::config::Role;
+use crate::demand::{touch_stream, wait_for_segments};
+use crate::paths::resolve;
+use crate::structures::{AppState, ResponseData};
+use crate::webhooks::sign;
+
+#[derive(Deserialize)]
+pub struct HlsQuery {
+    pub token: Option<String>
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct HlsUrl {
+    pub url: String,
+    pub expires: Option<u64>
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+fn token_claims(stream_id: &str, expiry: u64, ip: Option<IpAddr>) -> String {
+    format!("{}|{}|{}", stream_id, expiry, ip.map(|ip| ip.to_string()).unwrap_or_default())
+}
+
+pub fn sign_token(secret: &str, stream_id: &str, expiry: u64, ip: Option<IpAddr>) -> String {
+    let signature = sign(secret, token_claims(stream_id, expiry, ip).as_bytes());
+    format!("{}.{}.{}", expiry, if ip.is_some() { 1 } else { 0 }, signature)
+}
+
+pub fn verify_token(secret: &str, stream_id: &str, token: &str, client_ip: IpAddr) -> bool {
+    let parts: Vec<&str> = token.split('.').collect();
+
+    if parts.len() != 3 {
+        return false;
+    }
+
+    let expiry = match parts[0].parse::<u64>() {
+        Ok(expiry) => expiry,
+        Err(_) => return false,
+    };
+
+    if expiry < now() {
+        return false;
+    }
+
+    let ip = if parts[1] == "1" { Some(client_ip) } else { None };
+
+    let signature = match hex::decode(parts[2]) {
+        Ok(signature) => signature,
+        Err(_) => return false,
+    };
+
+    let mut mac = match Hmac::<Sha256>::new_from_slice(secret.as_bytes()) {
+        Ok(mac) => mac,
+        Err(_) => return false,
+    };
+    mac.update(token_claims(stream_id, expiry, ip).as_bytes());
+
+    mac.verify_slice(&signature).is_ok()
+}
+
+// appends the token to every URI line so variant playlists and segments can be fetched with it
+fn rewrite_playlist(playlist: &str, token: &str) -> String {
+    playlist
+        .lines()
+        .map(|line| {
+            if line.is_empty() || line.starts_with('#') {
+                line.to_string()
+            } else if line.contains('?') {
+                format!("{}&token={}", line, token)
+            } else {
+                format!("{}?token={}", line, token)
+            }
+        })
+        .collect::<Vec<String>>()
+        .join("\n") + "\n"
+}
+
+fn content_type(path: &str) -> &'static str {
+    if path.ends_with(".m3u8") || path.ends_with(".m3u") {
+        "application/vnd.apple.mpegurl"
+    } else if path.ends_with(".ts") {
+        "video/mp2t"
+    } else {
+        "application/octet-stream"
+    }
+}
+
+// `{id}/master.m3u` is the master playlist, `{id}/720p/...` the files of one variant
+fn rendition(relative: &str) -> &str {
+    let mut parts = relative.split('/').skip(1);
+
+    match (parts.next(), parts.next()) {
+        (Some(variant), Some(_)) => variant,
+        _ => "master",
+    }
+}
+
+pub async fn serve_hls(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Query(query): Query<HlsQuery>,
+    principal: Option<Extension<Principal>>,
+    headers: HeaderMap,
+    uri: Uri,
+) -> Response {
+    let relative = uri.path().trim_start_matches('/').to_string();
+
+    let path = match resolve(&relative) {
+        Some(path) if relative.contains('/') => path,
+        _ => return StatusCode::NOT_FOUND.into_response(),
+    };
+
+    let dir = relative.split('/').next().unwrap_or_default();
+    let is_playlist = relative.ends_with(".m3u8") || relative.ends_with(".m3u");
+
+    let streams_lock = state.streams.lock().await;
+    let owner = streams_lock
+        .values()
+        .find(|s| s.stream_type == "HLS" && s.id == dir)
+        .map(|s| s.owner.clone());
+    drop(streams_lock);
+
+    let token = match &state.config.hls.signing_secret {
+        Some(secret) => {
+            if owner.is_none() {
+                return StatusCode::NOT_FOUND.into_response();
+            }
+
+            match &query.token {
+                Some(token) if verify_token(secret, dir, token, addr.ip()) => Some(token.clone()),
+                _ => return (StatusCode::FORBIDDEN, "Invalid or expired token").into_response(),
+            }
+        }
+        // without signed URLs, enabling auth still keeps the output private: players send a viewer key like API clients do
+        None => {
+            if let Err(status) = check_access(&state.config, principal.as_ref().map(|Extension(principal)| principal), &headers, Role::Viewer) {
+                return reject(status, status.canonical_reason().unwrap_or_default());
+            }
+
+            None
+        }
+    };
+
+    let user_agent = headers.get(header::USER_AGENT).and_then(|v| v.to_str().ok()).unwrap_or_default();
+    let session = session_id(dir, token.as_deref(), addr.ip(), user_agent);
+
+    let (response, bytes) = deliver(&state, &relative, &path, is_playlist, token.as_deref()).await;
+
+    // files that don't belong to a configured stream aren't accounted
+    if let Some(owner) = &owner {
+        state.hls.record(Delivery {
+            stream: dir,
+            owner,
+            rendition: rendition(&relative),
+            kind: if is_playlist { HlsRequestKind::Playlist } else { HlsRequestKind::Segment },
+            status: response.status().as_u16(),
+            bytes,
+            client: addr.ip(),
+            session
+        });
+    }
+
+    response
+}
+
+// the response and the number of body bytes delivered
+async fn deliver(state: &AppState, relative: &str, path: &FsPath, is_playlist: bool, token: Option<&str>) -> (Response, u64) {
+    let dir = relative.split('/').next().unwrap_or_default();
+
+    // any fetch keeps an on-demand stream alive, and wakes it if it is idle
+    if touch_stream(state, dir).await && is_playlist && !wait_for_segments(state, dir, path).await {
+        return ((StatusCode::SERVICE_UNAVAILABLE, [(header::RETRY_AFTER, "2")], "Stream is starting").into_response(), 0);
+    }
+
+    let body = match tokio::fs::read(path).await {
+        Ok(body) => body,
+        Err(_) => return (StatusCode::NOT_FOUND.into_response(), 0),
+    };
+
+    let body = match (token, is_playlist) {
+        (Some(token), true) => rewrite_playlist(&String::from_utf8_lossy(&body), token).into_bytes(),
+        _ => body,
+    };
+
+    let bytes = body.len() as u64;
+
+    let response = (
+        [
+            (header::CONTENT_TYPE, content_type(relative)),
+            (header::CACHE_CONTROL, if is_playlist { "no-cache" } else { "max-age=3600" }),
+        ],
+        body,
+    ).into_response();
+
+    (response, bytes)
+}
+
+#[utoipa::path(
+    get,
+    path = "/streams/{id}/hls-url",
+    tag = "playback",
+    params(("id" = String, Path, description = "Stream ID")),
+    responses(
+        (status = 200, description = "Master playlist URL, signed when signing is enabled", body = ResponseData<HlsUrl>)
+    )
+)]
+pub async fn get_hls_url(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+) -> Json<ResponseData<HlsUrl>> {
+    let streams_lock = state.streams.lock().await;
+    let stream = streams_lock.values().find(|s| s.id == id && s.stream_type == "HLS").cloned();
+    drop(streams_lock);
+
+    let stream = match stream {
+        Some(stream) => stream,
+        None => {
+            return Json(ResponseData {
+                status: false,
+                message: String::from("Stream not found"),
+                data: None
+            });
+        }
+    };
+
+    let master = format!("/{}/master.m3u", stream.id);
+
+    let data = match &state.config.hls.signing_secret {
+        Some(secret) => {
+            let expiry = now() + state.config.hls.token_ttl;
+            let ip = if state.config.hls.bind_ip { Some(addr.ip()) } else { None };
+            let token = sign_token(secret, &stream.id, expiry, ip);
+
+            HlsUrl { url: format!("{}?token={}", master, token), expires: Some(expiry) }
+        }
+        None => HlsUrl { url: master, expires: None },
+    };
+
+    Json(ResponseData {
+        status: true,
+        message: String::from("Fetch Success"),
+        data: Some(data)
+    })
+}
@@ -0,0 +1,147 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use crate::auth::Principal;
+use crate::config::LimitsConfig;
+use crate::structures::{AppState, EncodeOptions, ResponseData, RtspStream};
+
+// default values
+const CAPACITY_RETRY_AFTER: u64 = 30;
+const QUOTA_RETRY_AFTER: u64 = 60;
+const MAX_BUCKETS: usize = 1024;
+const BUCKET_IDLE: Duration = Duration::from_secs(600);
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant
+}
+
+pub struct RateLimiter {
+    buckets: Mutex<HashMap<String, Bucket>>
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        RateLimiter { buckets: Mutex::new(HashMap::new()) }
+    }
+
+    // takes a token from the caller's bucket, or returns how many seconds until one is available
+    pub fn check(&self, key: &str, rate: f64, burst: u32) -> Result<(), u64> {
+        let burst = burst.max(1) as f64;
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+
+        if buckets.len() > MAX_BUCKETS {
+            buckets.retain(|_, bucket| now.duration_since(bucket.updated) < BUCKET_IDLE);
+        }
+
+        let bucket = buckets.entry(key.to_string()).or_insert(Bucket { tokens: burst, updated: now });
+
+        bucket.tokens = (bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * rate).min(burst);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(((1.0 - bucket.tokens) / rate).ceil().max(1.0) as u64)
+        }
+    }
+}
+
+pub fn reject(status: StatusCode, retry_after: u64, message: String) -> Response {
+    (
+        status,
+        [(header::RETRY_AFTER, retry_after.to_string())],
+        Json(ResponseData::<()> {
+            status: false,
+            message,
+            data: None
+        }),
+    ).into_response()
+}
+
+// a capacity or quota refusal, turned into a response with its Retry-After by the caller
+pub struct Refusal {
+    status: StatusCode,
+    retry_after: u64,
+    message: String
+}
+
+impl IntoResponse for Refusal {
+    fn into_response(self) -> Response {
+        reject(self.status, self.retry_after, self.message)
+    }
+}
+
+pub async fn rate_limit(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let rate = match state.config.limits.rate {
+        Some(rate) if rate > 0.0 => rate,
+        _ => return next.run(request).await,
+    };
+
+    // without auth every caller is the same anonymous principal, so fall back to the client address
+    let key = match request.extensions().get::<Principal>() {
+        Some(principal) if state.config.auth.enabled => format!("key:{}", principal.name),
+        _ => format!("ip:{}", addr.ip()),
+    };
+
+    if let Err(retry_after) = state.limiter.check(&key, rate, state.config.limits.burst) {
+        return reject(StatusCode::TOO_MANY_REQUESTS, retry_after, String::from("Rate limit exceeded"));
+    }
+
+    next.run(request).await
+}
+
+// number of encoders a stream runs
+pub fn transcode_cost(stream_type: &str, encode_options: &EncodeOptions) -> usize {
+    match encode_options {
+        EncodeOptions::NONE => 0,
+        EncodeOptions::MULTI if stream_type == "HLS" => 3,
+        _ => 1,
+    }
+}
+
+// checked with the streams lock held, `replacing` is the key the new stream will overwrite
+pub fn check_capacity(
+    config: &LimitsConfig,
+    streams: &HashMap<String, RtspStream>,
+    replacing: &str,
+    owner: &str,
+    cost: usize,
+) -> Result<(), Refusal> {
+    let others = || streams.iter().filter(|(key, _)| key.as_str() != replacing).map(|(_, stream)| stream);
+
+    if let Some(max) = config.per_key_max_streams {
+        if others().filter(|s| s.owner == owner).count() >= max {
+            return Err(Refusal { status: StatusCode::TOO_MANY_REQUESTS, retry_after: QUOTA_RETRY_AFTER, message: format!("Stream quota of {} reached for {}", max, owner) });
+        }
+    }
+
+    if let Some(max) = config.max_streams {
+        if others().count() >= max {
+            return Err(Refusal { status: StatusCode::SERVICE_UNAVAILABLE, retry_after: CAPACITY_RETRY_AFTER, message: format!("Server is at its limit of {} streams", max) });
+        }
+    }
+
+    if let Some(max) = config.max_transcodes {
+        let running: usize = others().map(|s| transcode_cost(&s.stream_type, &s.encode_options)).sum();
+
+        if cost > 0 && running + cost > max {
+            return Err(Refusal { status: StatusCode::SERVICE_UNAVAILABLE, retry_after: CAPACITY_RETRY_AFTER, message: format!("Server is at its limit of {} transcodes", max) });
+        }
+    }
+
+    Ok(())
+}
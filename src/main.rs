@@ -1,74 +1,247 @@
 mod streamer;
 mod stream_manager;
 mod structures;
+mod probe;
+mod metrics;
+mod events;
+mod webhooks;
+mod config;
+mod mqtt;
+mod auth;
+mod hls;
+mod signalling;
+mod tls;
+mod validation;
+mod paths;
+mod limits;
+mod api;
+mod accounting;
+mod openapi;
+mod playlist;
+mod bulk;
+mod cli;
+mod demand;
+mod scheduler;
+mod selector;
+mod viewers;
+mod ice;
+mod whep;
 
 use std::collections::HashMap;
-use std::sync::Arc;
-use axum::http::StatusCode;
-use axum::{Router};
-use axum::routing::{delete, get, get_service, post};
-use gst_plugin_webrtc_signalling::handlers::Handler;
-use gst_plugin_webrtc_signalling::server::Server;
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use axum::{Extension, Router};
+use axum_server::tls_rustls::RustlsConfig;
+use axum::middleware::from_fn_with_state;
+use axum::routing::{delete, get, patch, post};
 use gstreamer as gst;
 use gstreamer::prelude::*;
 use serde::{Deserialize, Serialize};
-use tokio::sync::Mutex;
-use tower_http::services::ServeDir;
-use stream_manager::{add_stream,get_streams,delete_stream};
-use structures::RtspStream;
+use tokio::sync::{broadcast, Mutex};
+use stream_manager::{add_stream,get_streams,delete_stream,get_stream};
+use probe::probe_stream;
+use metrics::{get_metrics, track_http, Metrics};
+use limits::{rate_limit, RateLimiter};
+use events::{get_events_token, stream_events};
+use auth::{authorize, authorize_query_token, cors_layer, Principal};
+use config::{load_config, Role};
+use hls::{get_hls_url, serve_hls};
+use signalling::{get_webrtc_token, run_signalling, run_signalling_tls, spawn_server};
+use mqtt::run_mqtt;
+use webhooks::{add_webhook, delete_webhook, get_deliveries, get_webhooks, run_dispatcher, Webhooks};
+use structures::{AppState, RtspStream};
+use accounting::HlsAccounting;
+use demand::{reap_idle, run_demands};
+use openapi::{openapi_json, ApiDoc};
+use scheduler::Scheduler;
+use utoipa::OpenApi;
+use utoipa_swagger_ui::SwaggerUi;
 use tokio::net::TcpListener;
-use tokio::task;
 
 
 #[tokio::main]
 async fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    if args.first().map(String::as_str) == Some("openapi") {
+        println!("{}", openapi_json());
+        return;
+    }
+
+    if let Some(result) = cli::run(&args).await {
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     gst::init().unwrap();
 
+    let config = Arc::new(load_config().unwrap());
+
     let streams : Arc<Mutex<HashMap<String, RtspStream>>> = Arc::new(Mutex::new(HashMap::new()));
+    let metrics = Arc::new(Metrics::new());
+    let (events, _) = broadcast::channel(256);
+    let (server_signalling, signalling_gate, signalling_demands) = spawn_server(config.signalling.secret.clone());
 
-    let serve_dir = get_service(ServeDir::new("./hls").append_index_html_on_directories(false))
-            .handle_error(|error| async move {
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    format!("Filesystem error: {}", error),
-                )
-            });
+    let state = AppState {
+        config: config.clone(),
+        streams: streams.clone(),
+        metrics: metrics.clone(),
+        events,
+        webhooks: Arc::new(Webhooks::new()),
+        signalling: signalling_gate.clone(),
+        limiter: Arc::new(RateLimiter::new()),
+        scheduler: Arc::new(Scheduler::new(&config.startup)),
+        hls: Arc::new(HlsAccounting::new()),
+    };
 
-        let app = Router::new()
-            .nest_service("/", serve_dir)
-            .route("/addStream", post(add_stream))
+    tokio::spawn(run_dispatcher(state.clone()));
+    tokio::spawn(run_demands(state.clone(), signalling_demands));
+    tokio::spawn(reap_idle(state.clone()));
+
+    if let Some(mqtt) = config.mqtt.clone() {
+        tokio::spawn(run_mqtt(state.clone(), mqtt));
+    }
+
+        let viewer_routes = Router::new()
             .route("/getStreams", get(get_streams))
+            .route("/streams/:id", get(get_stream))
+            .route("/metrics", get(get_metrics))
+            .route("/events/token", get(get_events_token))
+            .route("/streams/:id/hls-url", get(get_hls_url))
+            .route("/streams/:id/webrtc-token", get(get_webrtc_token))
+            .route("/streams/:id/ice-servers", get(ice::get_ice_servers))
+            .route("/whep/:id", post(whep::whep_offer))
+            .route("/whep/:id/:session", patch(whep::whep_candidates).delete(whep::whep_delete))
+            .route("/api/v1/streams", get(api::list_streams))
+            .route("/api/v1/streams/:id", get(api::show_stream))
+            .route("/api/v1/streams/:id/viewers", get(viewers::list_viewers))
+            .route_layer(from_fn_with_state((config.clone(), Role::Viewer), authorize));
+
+        // checked for a `?token=` before the usual API key headers
+        let event_routes = Router::new()
+            .route("/events", get(stream_events))
+            .route_layer(from_fn_with_state((config.clone(), Role::Viewer), authorize))
+            .route_layer(from_fn_with_state(config.clone(), authorize_query_token));
+
+        let operator_routes = Router::new()
+            .route("/addStream", post(add_stream))
             .route("/deleteStream", delete(delete_stream))
-            .with_state(streams.clone())
-            .layer(tower_http::cors::CorsLayer::new()
-                .allow_origin(tower_http::cors::Any)
-                .allow_methods(tower_http::cors::Any)
-                .allow_headers(tower_http::cors::Any)
-            );
+            .route("/probe", post(probe_stream))
+            .route("/api/v1/streams", post(api::create_stream).delete(bulk::delete_matching))
+            .route("/api/v1/streams/restart", post(bulk::restart_matching))
+            .route("/api/v1/streams/:id", patch(api::update_stream).delete(api::destroy_stream))
+            .route("/api/v1/streams/:id/restart", post(api::restart_stream))
+            .route("/api/v1/streams/:id/pause", post(api::pause_stream))
+            .route("/api/v1/streams/:id/resume", post(api::resume_stream))
+            .route("/api/v1/streams/import", post(bulk::import_streams))
+            .route("/api/v1/streams/export", get(bulk::export_streams))
+            .route("/streams/:id", patch(api::update_stream))
+            .route_layer(from_fn_with_state(state.clone(), rate_limit))
+            .route_layer(from_fn_with_state((config.clone(), Role::Operator), authorize));
+
+        let admin_routes = Router::new()
+            .route("/webhooks", post(add_webhook).get(get_webhooks))
+            .route("/webhooks/deliveries", get(get_deliveries))
+            .route("/webhooks/:id", delete(delete_webhook))
+            .route("/api/v1/streams/:id/viewers/:peer_id", delete(viewers::kick_viewer))
+            .route("/api/v1/usage", get(accounting::get_usage))
+            .route("/api/v1/usage/requests", get(accounting::get_requests))
+            .route_layer(from_fn_with_state((config.clone(), Role::Admin), authorize));
+
+        let app = Router::new()
+            .merge(viewer_routes)
+            .merge(event_routes)
+            .merge(operator_routes)
+            .merge(admin_routes)
+            .merge(SwaggerUi::new("/docs").url("/openapi.json", ApiDoc::openapi()))
+            .fallback(serve_hls)
+            .layer(from_fn_with_state(metrics.clone(), track_http))
+            .with_state(state)
+            .layer(cors_layer(&config.cors));
 
-    let listener = TcpListener::bind("127.0.0.1:5005").await.unwrap();
+    let http_addr: SocketAddr = config.listen.http.parse().expect("Invalid listen.http address");
 
-    let server_signalling = Server::spawn(Handler::new);
+    let listener_signalling = TcpListener::bind(config.listen.signalling.as_str()).await.unwrap();
 
-    let addr_signalling = format!("{}:{}", "127.0.0.1", "8443");
+    let http_tls = match &config.tls {
+        Some(tls_config) => {
+            // reqwest and axum-server pull in different rustls backends, so pick one explicitly
+            let _ = rustls::crypto::ring::default_provider().install_default();
 
-    let listener_signalling = TcpListener::bind(&addr_signalling).await.unwrap();
+            let server_config = tls::server_config(tls_config, None).unwrap();
+            let http_tls = RustlsConfig::from_config(Arc::new(server_config));
+
+            let admin_tls = match (&tls_config.client_ca, &tls_config.admin_addr) {
+                (Some(client_ca), Some(admin_addr)) => {
+                    let admin_config = tls::server_config(tls_config, Some(client_ca.as_str())).unwrap();
+                    let admin_tls = RustlsConfig::from_config(Arc::new(admin_config));
+                    let admin_addr: SocketAddr = admin_addr.parse().expect("Invalid tls.admin_addr address");
+
+                    // a verified client certificate stands in for an admin API key
+                    let admin_app = app.clone().layer(Extension(Principal {
+                        name: String::from("client-certificate"),
+                        role: Role::Admin,
+                    }));
+
+                    let server = axum_server::bind_rustls(admin_addr, admin_tls.clone());
+                    tokio::spawn(async move {
+                        if let Err(e) = server.serve(admin_app.into_make_service_with_connect_info::<SocketAddr>()).await {
+                            eprintln!("Admin server stopped: {}", e);
+                        }
+                    });
+
+                    Some(admin_tls)
+                }
+                _ => None,
+            };
+
+            let signalling_tls = match &tls_config.signalling_addr {
+                Some(signalling_addr) => {
+                    let acceptor = Arc::new(RwLock::new(tls::signalling_acceptor(tls_config).unwrap()));
+                    let listener = TcpListener::bind(signalling_addr.as_str()).await.unwrap();
+
+                    tokio::spawn(run_signalling_tls(listener, server_signalling.clone(), signalling_gate.clone(), acceptor.clone()));
+
+                    Some(acceptor)
+                }
+                None => None,
+            };
+
+            tokio::spawn(tls::watch_certificates(tls_config.clone(), http_tls.clone(), admin_tls, signalling_tls));
+
+            Some(http_tls)
+        }
+        None => None,
+    };
 
     println!("Server started successfully!");
 
+    let http_server = async {
+        match http_tls {
+            Some(http_tls) => {
+                axum_server::bind_rustls(http_addr, http_tls)
+                    .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+                    .await
+            }
+            None => {
+                let listener = TcpListener::bind(http_addr).await?;
+                axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await
+            }
+        }
+    };
+
     tokio::select! {
-        _ = axum::serve(listener, app) => {
+        result = http_server => {
+            if let Err(e) = result {
+                eprintln!("HTTP server error: {}", e);
+            }
             println!("HTTP server stopped");
         }
-        _ = async {
-            while let Ok((stream, _address)) = listener_signalling.accept().await {
-                let mut server_clone = server_signalling.clone();
-                task::spawn(async move {
-                    if let Err(e) = server_clone.accept_async(stream).await {
-                        eprintln!("Error accepting connection: {}", e);
-                    }
-                });
-            }
-        } => {}
+        _ = run_signalling(listener_signalling, server_signalling, signalling_gate) => {
+            println!("Signalling server stopped");
+        }
     }
 }
@@ -0,0 +1,251 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use axum::extract::{MatchedPath, Request, State};
+use axum::http::header;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use crate::accounting::HlsAccounting;
+use crate::structures::{AppState, RtspStream, StreamState};
+use crate::streamer::{pipeline_lost_packets, pipeline_queue_levels};
+use crate::viewers::webrtc_bytes_sent;
+
+const HTTP_BUCKETS: [f64; 10] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0];
+const SEGMENT_BUCKETS: [f64; 9] = [0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0];
+
+const STATES: [StreamState; 8] = [
+    StreamState::Idle,
+    StreamState::Queued,
+    StreamState::Starting,
+    StreamState::Running,
+    StreamState::Reconnecting,
+    StreamState::Paused,
+    StreamState::Stopped,
+    StreamState::Failed,
+];
+
+struct Histogram {
+    buckets: &'static [f64],
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64
+}
+
+impl Histogram {
+    fn new(buckets: &'static [f64]) -> Self {
+        Histogram {
+            buckets,
+            counts: vec![0; buckets.len()],
+            sum: 0.0,
+            count: 0
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        for (i, bound) in self.buckets.iter().enumerate() {
+            if value <= *bound {
+                self.counts[i] += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let separator = if labels.is_empty() { "" } else { "," };
+
+        for (bound, count) in self.buckets.iter().zip(self.counts.iter()) {
+            let _ = writeln!(out, "{}_bucket{{{}{}le=\"{}\"}} {}", name, labels, separator, bound, count);
+        }
+        let _ = writeln!(out, "{}_bucket{{{}{}le=\"+Inf\"}} {}", name, labels, separator, self.count);
+        let _ = writeln!(out, "{}_sum{{{}}} {}", name, labels, self.sum);
+        let _ = writeln!(out, "{}_count{{{}}} {}", name, labels, self.count);
+    }
+}
+
+pub struct Metrics {
+    http_requests: Mutex<HashMap<(String, String, u16), u64>>,
+    http_durations: Mutex<HashMap<(String, String), Histogram>>,
+    segment_latency: Mutex<HashMap<String, Histogram>>
+}
+
+pub fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+pub fn describe(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics {
+            http_requests: Mutex::new(HashMap::new()),
+            http_durations: Mutex::new(HashMap::new()),
+            segment_latency: Mutex::new(HashMap::new())
+        }
+    }
+
+    pub fn observe_http(&self, method: &str, path: &str, status: u16, seconds: f64) {
+        if let Ok(mut requests) = self.http_requests.lock() {
+            *requests.entry((method.to_string(), path.to_string(), status)).or_insert(0) += 1;
+        }
+
+        if let Ok(mut durations) = self.http_durations.lock() {
+            durations
+                .entry((method.to_string(), path.to_string()))
+                .or_insert_with(|| Histogram::new(&HTTP_BUCKETS))
+                .observe(seconds);
+        }
+    }
+
+    pub fn observe_segment_latency(&self, stream_id: &str, seconds: f64) {
+        if let Ok(mut latency) = self.segment_latency.lock() {
+            latency
+                .entry(stream_id.to_string())
+                .or_insert_with(|| Histogram::new(&SEGMENT_BUCKETS))
+                .observe(seconds);
+        }
+    }
+
+    pub fn render(&self, streams: &[RtspStream], hls: &HlsAccounting) -> String {
+        let mut out = String::new();
+
+        describe(&mut out, "rtsp_streams", "gauge", "Number of configured streams by state.");
+        for state in STATES {
+            let count = streams.iter().filter(|s| s.state == state).count();
+            let _ = writeln!(out, "rtsp_streams{{state=\"{}\"}} {}", state.as_str(), count);
+        }
+
+        let mut bitrate = String::new();
+        let mut fps = String::new();
+        let mut bytes = String::new();
+        let mut uptime = String::new();
+        let mut reconnects = String::new();
+        let mut lost = String::new();
+        let mut dropped = String::new();
+        let mut queue_buffers = String::new();
+        let mut queue_time = String::new();
+        let mut consumers = String::new();
+        let mut bytes_sent = String::new();
+
+        for stream in streams {
+            let labels = format!("stream=\"{}\",type=\"{}\"", escape(&stream.id), escape(&stream.stream_type));
+
+            let (stream_bitrate, stream_fps, stream_bytes, stream_dropped) = match stream.stats.lock() {
+                Ok(stats) => {
+                    let (b, f) = stats.rates();
+                    (b, f, stats.bytes, stats.dropped_frames())
+                }
+                Err(_) => (0, 0.0, 0, 0),
+            };
+
+            let _ = writeln!(bitrate, "rtsp_stream_ingest_bitrate_bps{{{}}} {}", labels, stream_bitrate);
+            let _ = writeln!(fps, "rtsp_stream_ingest_fps{{{}}} {}", labels, stream_fps);
+            let _ = writeln!(bytes, "rtsp_stream_ingest_bytes_total{{{}}} {}", labels, stream_bytes);
+            let _ = writeln!(uptime, "rtsp_stream_uptime_seconds{{{}}} {}", labels, stream.started_at.map(|s| s.elapsed().as_secs()).unwrap_or(0));
+            let _ = writeln!(reconnects, "rtsp_stream_reconnects_total{{{}}} {}", labels, stream.reconnects);
+
+            if let Some(pipeline) = &stream.pipeline {
+                let _ = writeln!(lost, "rtsp_stream_lost_packets_total{{{}}} {}", labels, pipeline_lost_packets(pipeline));
+                let _ = writeln!(dropped, "rtsp_stream_dropped_frames_total{{{}}} {}", labels, stream_dropped);
+
+                for (queue, level_buffers, level_time) in pipeline_queue_levels(pipeline) {
+                    let queue_labels = format!("{},queue=\"{}\"", labels, escape(&queue));
+                    let _ = writeln!(queue_buffers, "rtsp_encoder_queue_buffers{{{}}} {}", queue_labels, level_buffers);
+                    let _ = writeln!(queue_time, "rtsp_encoder_queue_seconds{{{}}} {}", queue_labels, level_time as f64 / 1_000_000_000.0);
+                }
+
+                if let Some(sent) = webrtc_bytes_sent(pipeline) {
+                    // counted from the sink's consumer-added/consumer-removed signals, its stats can hold sessions still negotiating
+                    let count = stream.viewers.lock().map(|viewers| viewers.count()).unwrap_or(0);
+
+                    let _ = writeln!(consumers, "rtsp_webrtc_consumers{{{}}} {}", labels, count);
+                    let _ = writeln!(bytes_sent, "rtsp_webrtc_bytes_sent_total{{{}}} {}", labels, sent);
+                }
+            }
+        }
+
+        describe(&mut out, "rtsp_stream_ingest_bitrate_bps", "gauge", "Current ingest bitrate in bits per second.");
+        out.push_str(&bitrate);
+        describe(&mut out, "rtsp_stream_ingest_fps", "gauge", "Current ingest frame rate.");
+        out.push_str(&fps);
+        describe(&mut out, "rtsp_stream_ingest_bytes_total", "counter", "Bytes received from the source.");
+        out.push_str(&bytes);
+        describe(&mut out, "rtsp_stream_uptime_seconds", "gauge", "Seconds since the current pipeline started.");
+        out.push_str(&uptime);
+        describe(&mut out, "rtsp_stream_reconnects_total", "counter", "Pipeline restarts after a source error.");
+        out.push_str(&reconnects);
+        describe(&mut out, "rtsp_stream_lost_packets_total", "counter", "Packets lost or late in the RTP jitterbuffer.");
+        out.push_str(&lost);
+        describe(&mut out, "rtsp_stream_dropped_frames_total", "counter", "Frames dropped by the decoders, encoders and sinks, from their QoS messages.");
+        out.push_str(&dropped);
+        describe(&mut out, "rtsp_encoder_queue_buffers", "gauge", "Encoded buffers waiting in the queue after each encoder.");
+        out.push_str(&queue_buffers);
+        describe(&mut out, "rtsp_encoder_queue_seconds", "gauge", "Encoded media time waiting in the queue after each encoder.");
+        out.push_str(&queue_time);
+        describe(&mut out, "rtsp_webrtc_consumers", "gauge", "Connected WebRTC consumers.");
+        out.push_str(&consumers);
+        describe(&mut out, "rtsp_webrtc_bytes_sent_total", "counter", "RTP bytes sent to WebRTC consumers.");
+        out.push_str(&bytes_sent);
+
+        describe(&mut out, "rtsp_hls_segment_write_latency_seconds", "histogram", "Delay between the end of a segment and it being written to the playlist.");
+        if let Ok(mut latency) = self.segment_latency.lock() {
+            latency.retain(|id, _| streams.iter().any(|s| &s.id == id));
+
+            for (id, histogram) in latency.iter() {
+                histogram.render(&mut out, "rtsp_hls_segment_write_latency_seconds", &format!("stream=\"{}\"", escape(id)));
+            }
+        }
+
+        hls.render(&mut out);
+
+        describe(&mut out, "rtsp_http_requests_total", "counter", "HTTP requests handled.");
+        if let Ok(requests) = self.http_requests.lock() {
+            for ((method, path, status), count) in requests.iter() {
+                let _ = writeln!(out, "rtsp_http_requests_total{{method=\"{}\",path=\"{}\",status=\"{}\"}} {}", escape(method), escape(path), status, count);
+            }
+        }
+
+        describe(&mut out, "rtsp_http_request_duration_seconds", "histogram", "HTTP request latency.");
+        if let Ok(durations) = self.http_durations.lock() {
+            for ((method, path), histogram) in durations.iter() {
+                histogram.render(&mut out, "rtsp_http_request_duration_seconds", &format!("method=\"{}\",path=\"{}\"", escape(method), escape(path)));
+            }
+        }
+
+        out
+    }
+}
+
+pub async fn track_http(State(metrics): State<Arc<Metrics>>, matched: Option<MatchedPath>, request: Request, next: Next) -> Response {
+    let method = request.method().to_string();
+    let path = matched.map(|m| m.as_str().to_string()).unwrap_or_else(|| String::from("unmatched"));
+    let started = Instant::now();
+
+    let response = next.run(request).await;
+
+    metrics.observe_http(&method, &path, response.status().as_u16(), started.elapsed().as_secs_f64());
+
+    response
+}
+
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "monitoring",
+    responses(
+        (status = 200, description = "Prometheus metrics", body = String, content_type = "text/plain")
+    )
+)]
+pub async fn get_metrics(State(state): State<AppState>) -> impl IntoResponse {
+    let streams_lock = state.streams.lock().await;
+    let streams: Vec<RtspStream> = streams_lock.values().cloned().collect();
+    drop(streams_lock);
+
+    let body = state.metrics.render(&streams, &state.hls);
+
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body)
+}
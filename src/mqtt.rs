@@ -0,0 +1,254 @@
+use std::sync::Arc;
+use std::time::Duration;
+use rumqttc::{AsyncClient, Event, LastWill, MqttOptions, Packet, QoS};
+use serde_json::json;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::Notify;
+use crate::config::MqttConfig;
+use crate::structures::{AppState, StreamEventKind, StreamState};
+
+fn topic(template: &str, id: &str) -> String {
+    template.replace("{id}", id)
+}
+
+fn availability(state: StreamState) -> &'static str {
+    if state == StreamState::Running { "online" } else { "offline" }
+}
+
+async fn publish_states(client: &AsyncClient, state: &AppState, config: &MqttConfig) {
+    let streams_lock = state.streams.lock().await;
+    let states: Vec<(String, StreamState)> = streams_lock.values().map(|s| (s.id.clone(), s.state)).collect();
+    drop(streams_lock);
+
+    for (id, stream_state) in states {
+        let _ = client.publish(topic(&config.state_topic, &id), QoS::AtLeastOnce, true, availability(stream_state)).await;
+    }
+}
+
+async fn publish_health(client: &AsyncClient, state: &AppState, config: &MqttConfig) {
+    let streams_lock = state.streams.lock().await;
+    let reports: Vec<(String, serde_json::Value)> = streams_lock.values().map(|s| {
+        let (bitrate, fps) = s.stats.lock().map(|stats| stats.rates()).unwrap_or((0, 0.0));
+
+        (s.id.clone(), json!({
+            "state": s.state,
+            "message": s.message,
+            "bitrate": bitrate,
+            "fps": fps,
+            "uptime": s.started_at.map(|started| started.elapsed().as_secs()),
+            "reconnects": s.reconnects,
+        }))
+    }).collect();
+    drop(streams_lock);
+
+    for (id, report) in reports {
+        let _ = client.publish(topic(&config.health_topic, &id), QoS::AtMostOnce, false, report.to_string()).await;
+    }
+}
+
+pub async fn run_mqtt(state: AppState, config: MqttConfig) {
+    let mut options = MqttOptions::new(config.client_id.clone(), config.host.clone(), config.port);
+    options.set_keep_alive(Duration::from_secs(30));
+    options.set_last_will(LastWill::new(config.server_topic.clone(), "offline", QoS::AtLeastOnce, true));
+
+    if let (Some(username), Some(password)) = (&config.username, &config.password) {
+        options.set_credentials(username.clone(), password.clone());
+    }
+
+    let (client, mut eventloop) = AsyncClient::new(options, 64);
+    let connected = Arc::new(Notify::new());
+    let notify = connected.clone();
+
+    tokio::spawn(async move {
+        loop {
+            match eventloop.poll().await {
+                Ok(Event::Incoming(Packet::ConnAck(_))) => notify.notify_one(),
+                Ok(_) => {}
+                Err(e) => {
+                    eprintln!("MQTT connection error: {}", e);
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                }
+            }
+        }
+    });
+
+    let mut receiver = state.events.subscribe();
+    let mut ticker = tokio::time::interval(Duration::from_secs(config.health_interval.max(1)));
+
+    loop {
+        tokio::select! {
+            _ = connected.notified() => {
+                let _ = client.publish(config.server_topic.clone(), QoS::AtLeastOnce, true, "online").await;
+                publish_states(&client, &state, &config).await;
+            }
+            received = receiver.recv() => {
+                let event = match received {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return,
+                };
+
+                let payload = serde_json::to_string(&event).unwrap_or_default();
+                let _ = client.publish(topic(&config.event_topic, &event.stream), QoS::AtLeastOnce, false, payload).await;
+
+                match event.kind {
+                    StreamEventKind::StateChanged { state: stream_state, .. } => {
+                        let _ = client.publish(topic(&config.state_topic, &event.stream), QoS::AtLeastOnce, true, availability(stream_state)).await;
+                    }
+                    StreamEventKind::Deleted => {
+                        // an empty retained payload clears the stream's retained state
+                        let _ = client.publish(topic(&config.state_topic, &event.stream), QoS::AtLeastOnce, true, Vec::<u8>::new()).await;
+                    }
+                    _ => {}
+                }
+            }
+            _ = ticker.tick() => {
+                publish_health(&client, &state, &config).await;
+            }
+        }
+    }
+}
+
+// needs a broker: `MQTT_TEST_BROKER=localhost:1883 cargo test --features mqtt-tests`
+#[cfg(all(test, feature = "mqtt-tests"))]
+mod tests {
+    use std::collections::{BTreeMap, HashMap};
+    use std::sync::Mutex;
+    use std::time::{SystemTime, UNIX_EPOCH};
+    use tokio::sync::broadcast;
+    use crate::accounting::HlsAccounting;
+    use crate::config::Config;
+    use crate::limits::RateLimiter;
+    use crate::metrics::Metrics;
+    use crate::playlist::HlsPlaylists;
+    use crate::scheduler::Scheduler;
+    use crate::signalling::spawn_server;
+    use crate::structures::{EncodeOptions, RtspStream, StreamEvent, StreamStats, WebRtcOptions};
+    use crate::viewers::ViewerRegistry;
+    use crate::webhooks::Webhooks;
+    use super::*;
+
+    fn broker() -> (String, u16) {
+        let address = std::env::var("MQTT_TEST_BROKER").unwrap_or_else(|_| String::from("localhost:1883"));
+        let (host, port) = address.rsplit_once(':').unwrap_or((&address, "1883"));
+
+        (host.to_string(), port.parse().expect("MQTT_TEST_BROKER port"))
+    }
+
+    fn stream(id: &str) -> RtspStream {
+        RtspStream {
+            id: id.to_string(),
+            rtsp: String::from("rtsp://camera.test/stream"),
+            stream_type: String::from("HLS"),
+            encode_options: EncodeOptions::NONE,
+            hls_options: None,
+            video_bitrate: None,
+            state: StreamState::Running,
+            status: true,
+            message: String::from("Started"),
+            pipeline: None,
+            started_at: Some(std::time::Instant::now()),
+            reconnects: 0,
+            generation: 0,
+            stats: Arc::new(Mutex::new(StreamStats::default())),
+            owner: String::from("test"),
+            playlists: Arc::new(Mutex::new(HlsPlaylists::new(5, 2))),
+            paused: false,
+            priority: 0,
+            name: None,
+            group: None,
+            labels: BTreeMap::new(),
+            on_demand: false,
+            last_demand: None,
+            wake: Arc::new(Notify::new()),
+            viewers: Arc::new(Mutex::new(ViewerRegistry::default())),
+            max_viewers: None,
+            webrtc_options: WebRtcOptions::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn publishes_event_health_and_server_topics() {
+        let (host, port) = broker();
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos();
+        let prefix = format!("rtsp-test-{}", nanos);
+        let id = "cam-1";
+
+        let config: MqttConfig = serde_json::from_value(json!({
+            "host": host,
+            "port": port,
+            "client_id": format!("{}-server", prefix),
+            "state_topic": format!("{}/streams/{{id}}/state", prefix),
+            "event_topic": format!("{}/streams/{{id}}/events", prefix),
+            "health_topic": format!("{}/streams/{{id}}/health", prefix),
+            "server_topic": format!("{}/server/status", prefix),
+            "health_interval": 1
+        })).unwrap();
+
+        let (_server, signalling, _demands) = spawn_server(None);
+        let (events, _) = broadcast::channel(16);
+        let app_config = Config::default();
+
+        let state = AppState {
+            config: Arc::new(app_config.clone()),
+            streams: Arc::new(tokio::sync::Mutex::new(HashMap::from([(String::from("rtsp://camera.test/stream"), stream(id))]))),
+            metrics: Arc::new(Metrics::new()),
+            events,
+            webhooks: Arc::new(Webhooks::new()),
+            signalling,
+            limiter: Arc::new(RateLimiter::new()),
+            scheduler: Arc::new(Scheduler::new(&app_config.startup)),
+            hls: Arc::new(HlsAccounting::new()),
+        };
+
+        let (client, mut eventloop) = AsyncClient::new(MqttOptions::new(format!("{}-observer", prefix), host, port), 64);
+        client.subscribe(format!("{}/#", prefix), QoS::AtLeastOnce).await.unwrap();
+
+        // wait for the subscription before the server publishes anything that isn't retained
+        loop {
+            if let Event::Incoming(Packet::SubAck(_)) = eventloop.poll().await.unwrap() {
+                break;
+            }
+        }
+
+        tokio::spawn(run_mqtt(state.clone(), config));
+
+        let mut received: HashMap<String, String> = HashMap::new();
+        let mut resend = tokio::time::interval(Duration::from_millis(500));
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(10);
+        let event_topic = format!("{}/streams/{}/events", prefix, id);
+        let wanted = [
+            format!("{}/server/status", prefix),
+            format!("{}/streams/{}/state", prefix, id),
+            format!("{}/streams/{}/health", prefix, id),
+            event_topic.clone(),
+        ];
+
+        while !wanted.iter().all(|topic| received.contains_key(topic)) {
+            tokio::select! {
+                _ = tokio::time::sleep_until(deadline) => panic!("missing topics, got {:?}", received.keys().collect::<Vec<_>>()),
+                // run_mqtt subscribes to events once it's running, so keep sending until one arrives
+                _ = resend.tick(), if !received.contains_key(&event_topic) => {
+                    let _ = state.events.send(StreamEvent::new(id, StreamEventKind::Warning { message: String::from("test") }));
+                }
+                polled = eventloop.poll() => {
+                    if let Event::Incoming(Packet::Publish(publish)) = polled.unwrap() {
+                        received.insert(publish.topic.clone(), String::from_utf8_lossy(&publish.payload).to_string());
+                    }
+                }
+            }
+        }
+
+        assert_eq!(received[&wanted[0]], "online");
+        assert_eq!(received[&wanted[1]], "online");
+
+        let health: serde_json::Value = serde_json::from_str(&received[&wanted[2]]).unwrap();
+        assert_eq!(health["state"], "running");
+        assert!(health["bitrate"].is_u64() && health["reconnects"] == 0);
+
+        let event: serde_json::Value = serde_json::from_str(&received[&event_topic]).unwrap();
+        assert_eq!(event["stream"], id);
+        assert_eq!(event["type"], "warning");
+        assert_eq!(event["message"], "test");
+    }
+}
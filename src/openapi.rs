@@ -0,0 +1,97 @@
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, Http, HttpAuthScheme, SecurityScheme};
+use utoipa::{Modify, OpenApi, ToSchema};
+use crate::structures::{CongestionControl, EncodeOptions, HLSOptions, IceOptions, IceTransportPolicy, RtspResponse, StreamData, StreamInfo, StreamPatch, StreamState, TurnServer, VideoCodec, WebRtcOptions};
+
+/// Envelope returned for errors and for responses without data.
+#[derive(ToSchema)]
+#[allow(dead_code)]
+pub struct ErrorResponse {
+    pub status: bool,
+    #[schema(example = "Stream not found")]
+    pub message: String,
+    #[schema(value_type = Option<Object>, nullable = true)]
+    pub data: Option<()>
+}
+
+struct SecuritySchemes;
+
+impl Modify for SecuritySchemes {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+
+        components.add_security_scheme("bearer", SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)));
+        components.add_security_scheme("api_key", SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("X-API-Key"))));
+    }
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "RTSP to Web", description = "Manage RTSP sources restreamed over WebRTC and HLS."),
+    paths(
+        crate::api::create_stream,
+        crate::api::list_streams,
+        crate::api::show_stream,
+        crate::api::update_stream,
+        crate::api::destroy_stream,
+        crate::api::restart_stream,
+        crate::api::pause_stream,
+        crate::api::resume_stream,
+        crate::bulk::import_streams,
+        crate::bulk::export_streams,
+        crate::bulk::restart_matching,
+        crate::bulk::delete_matching,
+        crate::viewers::list_viewers,
+        crate::viewers::kick_viewer,
+        crate::probe::probe_stream,
+        crate::stream_manager::add_stream,
+        crate::stream_manager::get_streams,
+        crate::stream_manager::get_stream,
+        crate::stream_manager::delete_stream,
+        crate::hls::get_hls_url,
+        crate::signalling::get_webrtc_token,
+        crate::ice::get_ice_servers,
+        crate::whep::whep_offer,
+        crate::whep::whep_candidates,
+        crate::whep::whep_delete,
+        crate::events::stream_events,
+        crate::events::get_events_token,
+        crate::metrics::get_metrics,
+        crate::accounting::get_usage,
+        crate::accounting::get_requests,
+        crate::webhooks::add_webhook,
+        crate::webhooks::get_webhooks,
+        crate::webhooks::delete_webhook,
+        crate::webhooks::get_deliveries,
+    ),
+    components(schemas(ErrorResponse, EncodeOptions, HLSOptions, WebRtcOptions, CongestionControl, VideoCodec, IceOptions, TurnServer, IceTransportPolicy, StreamData, StreamPatch, StreamInfo, StreamState, RtspResponse)),
+    modifiers(&SecuritySchemes),
+    security(("bearer" = []), ("api_key" = [])),
+    tags(
+        (name = "streams", description = "Stream management"),
+        (name = "playback", description = "Playback URLs and tokens"),
+        (name = "monitoring", description = "Events and metrics"),
+        (name = "webhooks", description = "Webhook subscriptions"),
+        (name = "legacy", description = "Original routes, kept for existing clients")
+    )
+)]
+pub struct ApiDoc;
+
+// printed by `rtsp openapi`, so CI can diff the generated spec against the committed one
+pub fn openapi_json() -> String {
+    ApiDoc::openapi().to_pretty_json().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn committed_spec_matches_the_api_types() {
+        let committed = include_str!("../openapi.json");
+
+        assert!(
+            openapi_json().trim_end() == committed.trim_end(),
+            "openapi.json is out of date, regenerate it with `cargo run -- openapi > openapi.json`"
+        );
+    }
+}
@@ -0,0 +1,77 @@
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+pub const HLS_ROOT: &str = "./hls";
+
+// a name is only used as a path segment if it can't escape or alias another directory
+fn safe_segment(name: &str) -> bool {
+    !name.is_empty()
+        && name != "."
+        && name != ".."
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+}
+
+// output directory of a stream, named after its ID so it never contains the source URL or its credentials
+pub fn stream_dir(id: &str) -> Result<PathBuf, String> {
+    if !safe_segment(id) {
+        return Err(format!("Invalid stream ID for output path: {}", id));
+    }
+
+    Ok(Path::new(HLS_ROOT).join(id))
+}
+
+// maps a request path like `<id>/720p/playlist.m3u8` to a file under the HLS root
+pub fn resolve(relative: &str) -> Option<PathBuf> {
+    let mut path = PathBuf::from(HLS_ROOT);
+
+    for segment in relative.split('/') {
+        if !safe_segment(segment) {
+            return None;
+        }
+        path.push(segment);
+    }
+
+    if path.components().skip(1).all(|c| matches!(c, Component::Normal(_))) {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+// directory name used before outputs were keyed by stream ID
+fn legacy_dir_name(rtsp: &str) -> String {
+    rtsp.replace(&['/', ':', '?', '&'][..], "_")
+}
+
+// moves the URL-named directory an older version left behind to the stream's ID-named one;
+// the legacy name may contain any character of the URL, so it is only checked to be a single plain component under the root
+pub fn migrate_legacy_dir(rtsp: &str, target: &Path) -> bool {
+    let name = legacy_dir_name(rtsp);
+    let mut components = Path::new(&name).components();
+
+    if !matches!((components.next(), components.next()), (Some(Component::Normal(_)), None)) {
+        return false;
+    }
+
+    let legacy = Path::new(HLS_ROOT).join(&name);
+
+    if legacy == target || !legacy.is_dir() {
+        return false;
+    }
+
+    if target.exists() {
+        eprintln!("Not migrating legacy HLS directory {}, {} already exists", legacy.display(), target.display());
+        return false;
+    }
+
+    match fs::rename(&legacy, target) {
+        Ok(()) => {
+            println!("Migrated legacy HLS directory {} to {}", legacy.display(), target.display());
+            true
+        }
+        Err(e) => {
+            eprintln!("Failed to migrate legacy HLS directory {}: {}", legacy.display(), e);
+            false
+        }
+    }
+}
@@ -0,0 +1,181 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const PLAYLIST_NAME: &str = "playlist.m3u8";
+
+#[derive(Debug)]
+struct Segment {
+    path: PathBuf,
+    duration: f64,
+    discontinuity: bool
+}
+
+// media playlist for one variant; unlike hlssink2's own it outlives the pipeline, so
+// segment numbering and the media sequence carry on across rebuilds
+#[derive(Debug)]
+struct MediaPlaylist {
+    segments: VecDeque<Segment>,
+    media_sequence: u64,
+    discontinuity_sequence: u64,
+    pending_discontinuity: bool
+}
+
+impl MediaPlaylist {
+    fn new() -> Self {
+        MediaPlaylist {
+            segments: VecDeque::new(),
+            media_sequence: 0,
+            discontinuity_sequence: 0,
+            pending_discontinuity: false
+        }
+    }
+
+    fn trim(&mut self, max_files: u32) {
+        while max_files > 0 && self.segments.len() > max_files as usize {
+            if let Some(segment) = self.segments.pop_front() {
+                if segment.discontinuity {
+                    self.discontinuity_sequence += 1;
+                }
+                self.media_sequence += 1;
+                let _ = fs::remove_file(&segment.path);
+            }
+        }
+    }
+
+    fn render(&self, target_duration: u32) -> String {
+        let longest = self.segments.iter().map(|s| s.duration.ceil() as u32).max().unwrap_or(0);
+
+        let mut playlist = String::new();
+        playlist.push_str("#EXTM3U\n");
+        playlist.push_str("#EXT-X-VERSION:3\n");
+        playlist.push_str(&format!("#EXT-X-TARGETDURATION:{}\n", target_duration.max(longest)));
+        playlist.push_str(&format!("#EXT-X-MEDIA-SEQUENCE:{}\n", self.media_sequence));
+
+        if self.discontinuity_sequence > 0 {
+            playlist.push_str(&format!("#EXT-X-DISCONTINUITY-SEQUENCE:{}\n", self.discontinuity_sequence));
+        }
+
+        playlist.push('\n');
+
+        for segment in &self.segments {
+            if segment.discontinuity {
+                playlist.push_str("#EXT-X-DISCONTINUITY\n");
+            }
+
+            let name = segment.path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+            playlist.push_str(&format!("#EXTINF:{:.3},\n{}\n", segment.duration, name));
+        }
+
+        playlist
+    }
+}
+
+#[derive(Debug)]
+pub struct HlsPlaylists {
+    max_files: u32,
+    target_duration: u32,
+    next_index: u64,
+    playlists: HashMap<PathBuf, MediaPlaylist>
+}
+
+impl HlsPlaylists {
+    pub fn new(max_files: u32, target_duration: u32) -> Self {
+        HlsPlaylists {
+            max_files,
+            target_duration,
+            next_index: 0,
+            playlists: HashMap::new()
+        }
+    }
+
+    // false until the first segment is written, a stream starting from scratch clears its output directory
+    pub fn is_started(&self) -> bool {
+        !self.playlists.is_empty()
+    }
+
+    pub fn reset(&mut self) {
+        self.next_index = 0;
+        self.playlists.clear();
+    }
+
+    // index the next pipeline's first segment gets, so file names never repeat
+    pub fn next_index(&self) -> u64 {
+        self.next_index
+    }
+
+    // called before a rebuild, the next segment of every variant is flagged as a discontinuity
+    pub fn mark_discontinuity(&mut self) {
+        for playlist in self.playlists.values_mut() {
+            playlist.pending_discontinuity = true;
+        }
+    }
+
+    // segments currently listed across every variant
+    pub fn segment_count(&self) -> usize {
+        self.playlists.values().map(|playlist| playlist.segments.len()).sum()
+    }
+
+    // forgets variants whose directory was removed because the quality ladder changed
+    pub fn prune(&mut self) {
+        self.playlists.retain(|dir, _| dir.is_dir());
+    }
+
+    pub fn set_options(&mut self, max_files: u32, target_duration: u32) {
+        self.max_files = max_files;
+        self.target_duration = target_duration;
+
+        let dirs: Vec<PathBuf> = self.playlists.keys().cloned().collect();
+
+        for dir in dirs {
+            if let Some(playlist) = self.playlists.get_mut(&dir) {
+                playlist.trim(max_files);
+            }
+            self.write(&dir);
+        }
+    }
+
+    pub fn add_segment(&mut self, location: &str, duration: f64, index: Option<u64>) {
+        let path = PathBuf::from(location);
+        let dir = match path.parent() {
+            Some(dir) => dir.to_path_buf(),
+            None => return,
+        };
+
+        if let Some(index) = index {
+            self.next_index = self.next_index.max(index + 1);
+        }
+
+        let max_files = self.max_files;
+        let playlist = self.playlists.entry(dir.clone()).or_insert_with(MediaPlaylist::new);
+
+        let discontinuity = playlist.pending_discontinuity;
+        playlist.pending_discontinuity = false;
+        playlist.segments.push_back(Segment { path, duration, discontinuity });
+        playlist.trim(max_files);
+
+        self.write(&dir);
+    }
+
+    fn write(&self, dir: &Path) {
+        let playlist = match self.playlists.get(dir) {
+            Some(playlist) => playlist,
+            None => return,
+        };
+
+        // written next to the target and renamed so readers never see a partial playlist
+        let target = dir.join(PLAYLIST_NAME);
+        let temporary = dir.join(format!(".{}.tmp", PLAYLIST_NAME));
+
+        if fs::write(&temporary, playlist.render(self.target_duration)).is_ok() {
+            let _ = fs::rename(&temporary, &target);
+        }
+    }
+}
+
+// segment number from a file name like `segment00042.ts`
+pub fn segment_index(location: &str) -> Option<u64> {
+    let stem = Path::new(location).file_stem()?.to_str()?;
+    let digits: String = stem.chars().rev().take_while(|c| c.is_ascii_digit()).collect::<Vec<_>>().into_iter().rev().collect();
+    digits.parse().ok()
+}
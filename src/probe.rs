@@ -0,0 +1,296 @@
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::Json;
+use gstreamer as gst;
+use gstreamer::prelude::*;
+use crate::structures::{AppState, ProbeAudio, ProbeData, ProbeResponse, ProbeVideo, ResponseData};
+use crate::openapi::ErrorResponse;
+use crate::validation::{reject_source, validate_source};
+
+// default values
+const PROBE_TIMEOUT: u64 = 10;
+const MAX_PROBE_TIMEOUT: u64 = 60;
+
+enum ProbeEvent {
+    // a video pad was exposed, its caps follow once parsebin knows them
+    VideoPad,
+    Video(usize, gst::Caps),
+    Audio(gst::Caps),
+    NoMorePads,
+}
+
+fn caps_string_field(structure: &gst::StructureRef, field: &str) -> Option<String> {
+    structure.get::<String>(field).ok()
+}
+
+fn video_from_caps(caps: &gst::Caps) -> Option<ProbeVideo> {
+    let structure = caps.structure(0)?;
+
+    let framerate = structure.get::<gst::Fraction>("framerate").ok()
+        .filter(|f| f.denom() != 0)
+        .map(|f| f.numer() as f64 / f.denom() as f64);
+
+    Some(ProbeVideo {
+        codec: structure.name().to_string(),
+        width: structure.get::<i32>("width").ok(),
+        height: structure.get::<i32>("height").ok(),
+        framerate,
+        profile: caps_string_field(structure, "profile"),
+        level: caps_string_field(structure, "level"),
+        caps: caps.to_string(),
+    })
+}
+
+fn audio_from_caps(caps: &gst::Caps) -> Option<ProbeAudio> {
+    let structure = caps.structure(0)?;
+
+    Some(ProbeAudio {
+        encoding: caps_string_field(structure, "encoding-name").unwrap_or_else(|| String::from("unknown")),
+        clock_rate: structure.get::<i32>("clock-rate").ok(),
+        channels: caps_string_field(structure, "encoding-params"),
+        caps: caps.to_string(),
+    })
+}
+
+fn run_probe(rtsp: &str, timeout: Duration) -> Result<ProbeResponse, String> {
+    let pipeline = gst::Pipeline::new();
+
+    let src = match gst::ElementFactory::make("rtspsrc").build() {
+        Ok(element) => element,
+        Err(_) => return Err("Failed to create rtspsrc".to_string()),
+    };
+    src.set_property("location", rtsp);
+
+    if let Err(e) = pipeline.add_many(&[&src]) {
+        return Err(format!("Failed to add elements to pipeline: {:?}", e));
+    }
+
+    let (tx, rx) = mpsc::channel::<ProbeEvent>();
+
+    let pipe = pipeline.clone();
+    let pad_tx = tx.clone();
+    let pad_count = Arc::new(AtomicUsize::new(0));
+
+    src.connect("pad-added", false, move |values| {
+        let pad = match values[1].get::<gst::Pad>() {
+            Ok(pad) => pad,
+            Err(_) => return None,
+        };
+
+        let caps = pad.query_caps(None);
+        let media = caps.structure(0)
+            .and_then(|s| s.get::<String>("media").ok())
+            .unwrap_or_default();
+
+        let fakesink = match gst::ElementFactory::make("fakesink").build() {
+            Ok(element) => element,
+            Err(_) => return None,
+        };
+
+        if media == "video" {
+            let parse = match gst::ElementFactory::make("parsebin").build() {
+                Ok(element) => element,
+                Err(_) => return None,
+            };
+
+            if pipe.add_many(&[&parse, &fakesink]).is_err() {
+                return None;
+            }
+
+            let index = pad_count.fetch_add(1, Ordering::SeqCst);
+            let _ = pad_tx.send(ProbeEvent::VideoPad);
+
+            let video_tx = pad_tx.clone();
+            let f = fakesink.clone();
+
+            parse.connect("pad-added", false, move |values| {
+                let pad = match values[1].get::<gst::Pad>() {
+                    Ok(pad) => pad,
+                    Err(_) => return None,
+                };
+
+                let probe_tx = video_tx.clone();
+
+                pad.add_probe(gst::PadProbeType::EVENT_DOWNSTREAM, move |_pad, info| {
+                    if let Some(gst::PadProbeData::Event(ref event)) = info.data {
+                        if let gst::EventView::Caps(caps) = event.view() {
+                            let _ = probe_tx.send(ProbeEvent::Video(index, caps.caps().to_owned()));
+                        }
+                    }
+
+                    gst::PadProbeReturn::Ok
+                });
+
+                if let Some(sink_pad) = f.static_pad("sink") {
+                    if !sink_pad.is_linked() {
+                        let _ = pad.link(&sink_pad);
+                    }
+                }
+
+                None
+            });
+
+            let _ = fakesink.sync_state_with_parent();
+            let _ = parse.sync_state_with_parent();
+
+            if let Some(sink_pad) = parse.static_pad("sink") {
+                let _ = pad.link(&sink_pad);
+            }
+        } else {
+            if pipe.add_many(&[&fakesink]).is_err() {
+                return None;
+            }
+
+            let _ = fakesink.sync_state_with_parent();
+
+            if let Some(sink_pad) = fakesink.static_pad("sink") {
+                let _ = pad.link(&sink_pad);
+            }
+
+            if media == "audio" {
+                let _ = pad_tx.send(ProbeEvent::Audio(caps));
+            }
+        }
+
+        None
+    });
+
+    let no_more_tx = tx.clone();
+
+    src.connect("no-more-pads", false, move |_| {
+        let _ = no_more_tx.send(ProbeEvent::NoMorePads);
+        None
+    });
+
+    drop(tx);
+
+    let mut response = ProbeResponse {
+        reachable: false,
+        authorized: true,
+        video: None,
+        audio: vec![],
+    };
+
+    if pipeline.set_state(gst::State::Playing).is_err() {
+        let _ = pipeline.set_state(gst::State::Null);
+        return Err("Failed to start probe pipeline".to_string());
+    }
+
+    let bus = match pipeline.bus() {
+        Some(bus) => bus,
+        None => {
+            let _ = pipeline.set_state(gst::State::Null);
+            return Err("Bus not initialized".to_string());
+        }
+    };
+
+    let deadline = Instant::now() + timeout;
+    let mut no_more_pads = false;
+    let mut video_pads = 0;
+    let mut videos_ready = HashSet::new();
+    let mut result: Result<(), String> = Err("Probe timed out".to_string());
+
+    while Instant::now() < deadline {
+        if let Some(msg) = bus.timed_pop_filtered(
+            gst::ClockTime::from_mseconds(100),
+            &[gst::MessageType::Error],
+        ) {
+            if let gst::MessageView::Error(err) = msg.view() {
+                if err.error().matches(gst::ResourceError::NotAuthorized) {
+                    response.reachable = true;
+                    response.authorized = false;
+                }
+                result = Err(err.error().to_string());
+                break;
+            }
+        }
+
+        while let Ok(event) = rx.try_recv() {
+            response.reachable = true;
+
+            match event {
+                ProbeEvent::VideoPad => {
+                    video_pads += 1;
+                }
+                ProbeEvent::Video(index, caps) => {
+                    response.video = video_from_caps(&caps);
+
+                    if response.video.as_ref().map(|v| v.width.is_some()).unwrap_or(false) {
+                        videos_ready.insert(index);
+                    }
+                }
+                ProbeEvent::Audio(caps) => {
+                    if let Some(audio) = audio_from_caps(&caps) {
+                        response.audio.push(audio);
+                    }
+                }
+                ProbeEvent::NoMorePads => {
+                    no_more_pads = true;
+                }
+            }
+        }
+
+        // audio caps are known when rtspsrc exposes the pad, video caps once parsebin has seen the stream headers
+        if no_more_pads && videos_ready.len() == video_pads {
+            result = Ok(());
+            break;
+        }
+    }
+
+    let _ = pipeline.set_state(gst::State::Null);
+
+    match result {
+        Ok(()) => Ok(response),
+        Err(_) if !response.authorized => Ok(response),
+        Err(e) => Err(e),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/probe",
+    tag = "streams",
+    request_body = ProbeData,
+    responses(
+        (status = 200, description = "Probe result", body = ResponseData<ProbeResponse>),
+        (status = 422, description = "The source URL was rejected", body = ErrorResponse)
+    )
+)]
+pub async fn probe_stream(State(state): State<AppState>, Json(payload): Json<ProbeData>) -> Result<Json<ResponseData<ProbeResponse>>, (StatusCode, Json<ResponseData<ProbeResponse>>)> {
+    validate_source(&state.config.sources, &payload.rtsp).await.map_err(reject_source)?;
+
+    let timeout = Duration::from_secs(payload.timeout.unwrap_or(PROBE_TIMEOUT).clamp(1, MAX_PROBE_TIMEOUT));
+
+    let rtsp = payload.rtsp.clone();
+    let result = tokio::task::spawn_blocking(move || run_probe(rtsp.as_str(), timeout)).await;
+
+    Ok(match result {
+        Ok(Ok(probe)) => {
+            let message = if probe.authorized {
+                String::from("Probe Success")
+            } else {
+                String::from("Unauthorized")
+            };
+
+            Json(ResponseData {
+                status: probe.authorized,
+                message,
+                data: Some(probe),
+            })
+        }
+        Ok(Err(e)) => Json(ResponseData {
+            status: false,
+            message: e,
+            data: None,
+        }),
+        Err(e) => Json(ResponseData {
+            status: false,
+            message: format!("Probe task failed: {}", e),
+            data: None,
+        }),
+    })
+}
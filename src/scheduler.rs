@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
+use url::Url;
+use crate::config::StartupConfig;
+
+struct Waiter {
+    seq: u64,
+    stream_id: String,
+    host: String,
+    priority: i32,
+    grant: oneshot::Sender<()>
+}
+
+#[derive(Default)]
+struct SchedulerState {
+    seq: u64,
+    starting: usize,
+    per_host: HashMap<String, usize>,
+    queue: Vec<Waiter>
+}
+
+// limits how many pipelines are being brought up at once, overall and per camera host
+pub struct Scheduler {
+    parallelism: usize,
+    per_host: usize,
+    state: Mutex<SchedulerState>
+}
+
+// a start slot, released when dropped
+pub struct StartPermit {
+    scheduler: Arc<Scheduler>,
+    host: String
+}
+
+impl Drop for StartPermit {
+    fn drop(&mut self) {
+        let mut state = self.scheduler.state.lock().unwrap();
+        self.scheduler.release(&mut state, &self.host);
+        self.scheduler.dispatch(&mut state);
+    }
+}
+
+pub fn source_host(rtsp: &str) -> String {
+    Url::parse(rtsp).ok()
+        .and_then(|url| url.host_str().map(|host| host.to_ascii_lowercase()))
+        .unwrap_or_else(|| rtsp.to_string())
+}
+
+impl Scheduler {
+    pub fn new(config: &StartupConfig) -> Self {
+        Scheduler {
+            parallelism: config.parallelism.max(1),
+            per_host: config.per_host.max(1),
+            state: Mutex::new(SchedulerState::default())
+        }
+    }
+
+    fn has_room(&self, state: &SchedulerState, host: &str) -> bool {
+        state.starting < self.parallelism && state.per_host.get(host).copied().unwrap_or(0) < self.per_host
+    }
+
+    fn take(&self, state: &mut SchedulerState, host: &str) {
+        state.starting += 1;
+        *state.per_host.entry(host.to_string()).or_insert(0) += 1;
+    }
+
+    fn release(&self, state: &mut SchedulerState, host: &str) {
+        state.starting = state.starting.saturating_sub(1);
+
+        if let Some(count) = state.per_host.get_mut(host) {
+            *count = count.saturating_sub(1);
+
+            if *count == 0 {
+                state.per_host.remove(host);
+            }
+        }
+    }
+
+    fn sort(queue: &mut [Waiter]) {
+        queue.sort_by(|a, b| b.priority.cmp(&a.priority).then(a.seq.cmp(&b.seq)));
+    }
+
+    // grants slots in priority order, skipping waiters whose host is already at its limit
+    fn dispatch(&self, state: &mut SchedulerState) {
+        Self::sort(&mut state.queue);
+
+        let mut index = 0;
+
+        while index < state.queue.len() && state.starting < self.parallelism {
+            if !self.has_room(state, &state.queue[index].host) {
+                index += 1;
+                continue;
+            }
+
+            let waiter = state.queue.remove(index);
+            self.take(state, &waiter.host);
+
+            // the supervisor stopped waiting, hand the slot straight back
+            if waiter.grant.send(()).is_err() {
+                self.release(state, &waiter.host);
+            }
+        }
+    }
+
+    pub fn try_acquire(self: &Arc<Self>, rtsp: &str) -> Option<StartPermit> {
+        let host = source_host(rtsp);
+        let mut state = self.state.lock().unwrap();
+
+        if !state.queue.is_empty() || !self.has_room(&state, &host) {
+            return None;
+        }
+
+        self.take(&mut state, &host);
+
+        Some(StartPermit { scheduler: self.clone(), host })
+    }
+
+    pub async fn acquire(self: &Arc<Self>, stream_id: &str, rtsp: &str, priority: i32) -> StartPermit {
+        let host = source_host(rtsp);
+        let (grant, granted) = oneshot::channel();
+
+        {
+            let mut state = self.state.lock().unwrap();
+            state.seq += 1;
+
+            let seq = state.seq;
+            state.queue.push(Waiter { seq, stream_id: stream_id.to_string(), host: host.clone(), priority, grant });
+            self.dispatch(&mut state);
+        }
+
+        let _ = granted.await;
+
+        StartPermit { scheduler: self.clone(), host }
+    }
+
+    // 1-based place in the start queue
+    pub fn position(&self, stream_id: &str) -> Option<usize> {
+        let mut state = self.state.lock().unwrap();
+        Self::sort(&mut state.queue);
+
+        state.queue.iter().position(|w| w.stream_id == stream_id).map(|p| p + 1)
+    }
+}
@@ -0,0 +1,261 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use axum::http::HeaderValue;
+use axum::response::Response;
+use serde::Deserialize;
+use utoipa::IntoParams;
+use crate::structures::RtspStream;
+
+// default values
+const MAX_PER_PAGE: usize = 500;
+const MAX_LABELS: usize = 32;
+const MAX_LABEL_LENGTH: usize = 63;
+const MAX_NAME_LENGTH: usize = 256;
+
+#[derive(Debug, Clone)]
+enum Requirement {
+    Equals(String, String),
+    NotEquals(String, String),
+    In(String, Vec<String>),
+    NotIn(String, Vec<String>),
+    Exists(String),
+    Missing(String)
+}
+
+// Kubernetes-style label selector, e.g. `site=hq,floor!=2,zone in (a,b),!decommissioned`
+#[derive(Debug, Clone, Default)]
+pub struct Selector {
+    requirements: Vec<Requirement>
+}
+
+impl Selector {
+    pub fn parse(input: &str) -> Result<Selector, String> {
+        let mut requirements = vec![];
+
+        for term in split_terms(input)? {
+            let term = term.trim();
+
+            if !term.is_empty() {
+                requirements.push(parse_requirement(term)?);
+            }
+        }
+
+        Ok(Selector { requirements })
+    }
+
+    pub fn matches(&self, labels: &BTreeMap<String, String>) -> bool {
+        self.requirements.iter().all(|requirement| match requirement {
+            Requirement::Equals(key, value) => labels.get(key) == Some(value),
+            Requirement::NotEquals(key, value) => labels.get(key) != Some(value),
+            Requirement::In(key, values) => labels.get(key).map(|l| values.contains(l)).unwrap_or(false),
+            Requirement::NotIn(key, values) => labels.get(key).map(|l| !values.contains(l)).unwrap_or(true),
+            Requirement::Exists(key) => labels.contains_key(key),
+            Requirement::Missing(key) => !labels.contains_key(key),
+        })
+    }
+}
+
+// commas inside an `in (...)` set don't separate terms
+fn split_terms(input: &str) -> Result<Vec<&str>, String> {
+    let mut terms = vec![];
+    let mut depth = 0;
+    let mut start = 0;
+
+    for (i, c) in input.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 0 => return Err(String::from("Unexpected ')' in selector")),
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                terms.push(&input[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+
+    if depth != 0 {
+        return Err(String::from("Unclosed '(' in selector"));
+    }
+
+    terms.push(&input[start..]);
+    Ok(terms)
+}
+
+fn parse_requirement(term: &str) -> Result<Requirement, String> {
+    if let Some(key) = term.strip_prefix('!') {
+        return Ok(Requirement::Missing(label_key(key)?));
+    }
+
+    if let Some((key, value)) = term.split_once("!=") {
+        return Ok(Requirement::NotEquals(label_key(key)?, label_value(value)?));
+    }
+
+    if let Some((key, value)) = term.split_once("==").or_else(|| term.split_once('=')) {
+        return Ok(Requirement::Equals(label_key(key)?, label_value(value)?));
+    }
+
+    let (key, rest) = match term.split_once(char::is_whitespace) {
+        Some((key, rest)) => (key, rest.trim()),
+        None => return Ok(Requirement::Exists(label_key(term)?)),
+    };
+
+    let (negated, set) = if let Some(set) = rest.strip_prefix("notin") {
+        (true, set)
+    } else if let Some(set) = rest.strip_prefix("in") {
+        (false, set)
+    } else {
+        return Err(format!("Invalid selector term '{}'", term));
+    };
+
+    let values = set.trim()
+        .strip_prefix('(')
+        .and_then(|s| s.strip_suffix(')'))
+        .ok_or_else(|| format!("Expected a (value, ...) set in '{}'", term))?
+        .split(',')
+        .map(label_value)
+        .collect::<Result<Vec<String>, String>>()?;
+
+    let key = label_key(key)?;
+
+    Ok(if negated { Requirement::NotIn(key, values) } else { Requirement::In(key, values) })
+}
+
+fn valid_label(text: &str) -> bool {
+    text.len() <= MAX_LABEL_LENGTH && text.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '/'))
+}
+
+fn label_key(key: &str) -> Result<String, String> {
+    let key = key.trim();
+
+    if key.is_empty() || !valid_label(key) {
+        return Err(format!("Invalid label key '{}'", key));
+    }
+
+    Ok(key.to_string())
+}
+
+fn label_value(value: &str) -> Result<String, String> {
+    let value = value.trim();
+
+    if !valid_label(value) {
+        return Err(format!("Invalid label value '{}'", value));
+    }
+
+    Ok(value.to_string())
+}
+
+pub fn validate_metadata(name: Option<&String>, group: Option<&String>, labels: Option<&BTreeMap<String, String>>) -> Result<(), String> {
+    if name.map(|n| n.chars().count() > MAX_NAME_LENGTH || n.chars().any(char::is_control)).unwrap_or(false) {
+        return Err(format!("name must be at most {} characters, without control characters", MAX_NAME_LENGTH));
+    }
+
+    if let Some(group) = group {
+        if group.is_empty() || !valid_label(group) {
+            return Err(format!("Invalid group '{}'", group));
+        }
+    }
+
+    if let Some(labels) = labels {
+        if labels.len() > MAX_LABELS {
+            return Err(format!("At most {} labels are allowed", MAX_LABELS));
+        }
+
+        for (key, value) in labels {
+            if label_key(key)? != *key || label_value(value)? != *value {
+                return Err(format!("Label '{}' has surrounding whitespace", key));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Deserialize, IntoParams, Default)]
+#[into_params(parameter_in = Query)]
+pub struct StreamQuery {
+    /// label selector, e.g. `site=hq,floor!=2,zone in (a,b),!decommissioned`
+    pub selector: Option<String>,
+    pub group: Option<String>,
+    /// queued, starting, running, reconnecting, paused, stopped or failed
+    pub state: Option<String>,
+    /// HLS or WEBRTC
+    pub stream_type: Option<String>,
+    /// id, name, group, state, stream_type, priority or uptime
+    pub sort: Option<String>,
+    /// asc or desc
+    pub order: Option<String>,
+    /// 1-based page number, used with `per_page`
+    pub page: Option<usize>,
+    /// page size, everything is returned when omitted
+    pub per_page: Option<usize>
+}
+
+fn compare(sort: &str, a: &RtspStream, b: &RtspStream) -> Ordering {
+    match sort {
+        "name" => a.name.cmp(&b.name),
+        "group" => a.group.cmp(&b.group),
+        "state" => a.state.as_str().cmp(b.state.as_str()),
+        "stream_type" => a.stream_type.cmp(&b.stream_type),
+        "priority" => a.priority.cmp(&b.priority),
+        "uptime" => a.started_at.map(|s| s.elapsed()).cmp(&b.started_at.map(|s| s.elapsed())),
+        _ => Ordering::Equal,
+    }
+}
+
+impl StreamQuery {
+    pub fn has_filter(&self) -> bool {
+        self.selector.as_deref().map(|s| !s.trim().is_empty()).unwrap_or(false)
+            || self.group.is_some()
+            || self.state.is_some()
+            || self.stream_type.is_some()
+    }
+
+    // filters and sorts; pagination is left to the caller so it can report the total
+    pub fn select<'a>(&self, streams: impl Iterator<Item = &'a RtspStream>) -> Result<Vec<&'a RtspStream>, String> {
+        let selector = Selector::parse(self.selector.as_deref().unwrap_or_default())?;
+
+        let sort = self.sort.as_deref().unwrap_or("id");
+        if !matches!(sort, "id" | "name" | "group" | "state" | "stream_type" | "priority" | "uptime") {
+            return Err(format!("Unknown sort field '{}'", sort));
+        }
+
+        let descending = match self.order.as_deref() {
+            None | Some("asc") => false,
+            Some("desc") => true,
+            Some(order) => return Err(format!("Unknown order '{}', expected asc or desc", order)),
+        };
+
+        let mut selected: Vec<&RtspStream> = streams
+            .filter(|s| selector.matches(&s.labels))
+            .filter(|s| self.group.as_ref().map(|g| s.group.as_ref() == Some(g)).unwrap_or(true))
+            .filter(|s| self.state.as_ref().map(|state| s.state.as_str().eq_ignore_ascii_case(state)).unwrap_or(true))
+            .filter(|s| self.stream_type.as_ref().map(|t| s.stream_type.eq_ignore_ascii_case(t)).unwrap_or(true))
+            .collect();
+
+        selected.sort_by(|a, b| {
+            let ordering = compare(sort, a, b).then_with(|| a.id.cmp(&b.id));
+            if descending { ordering.reverse() } else { ordering }
+        });
+
+        Ok(selected)
+    }
+
+    pub fn page<T>(&self, items: Vec<T>) -> Vec<T> {
+        match self.per_page {
+            Some(per_page) => {
+                let per_page = per_page.clamp(1, MAX_PER_PAGE);
+                let skip = self.page.unwrap_or(1).max(1).saturating_sub(1).saturating_mul(per_page);
+
+                items.into_iter().skip(skip).take(per_page).collect()
+            }
+            None => items,
+        }
+    }
+}
+
+// the total before pagination, so clients can page through the list
+pub fn with_total(mut response: Response, total: usize) -> Response {
+    response.headers_mut().insert("x-total-count", HeaderValue::from(total));
+    response
+}
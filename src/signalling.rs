@@ -0,0 +1,660 @@
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use axum::extract::{Path, State};
+use axum::Json;
+use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use futures::StreamExt;
+use gst_plugin_webrtc_signalling::handlers::Handler;
+use gst_plugin_webrtc_signalling::server::Server;
+use gst_plugin_webrtc_signalling_protocol::{EndSessionMessage, IncomingMessage, OutgoingMessage, PeerRole};
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use serde_json::{json, Value};
+use utoipa::ToSchema;
+use sha2::Sha256;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::net::TcpListener;
+use tokio::task;
+use tokio_rustls::TlsAcceptor;
+use crate::config::Config;
+use crate::structures::{AppState, ResponseData};
+use crate::webhooks::sign;
+
+// default values
+const MAX_HANDSHAKE: usize = 8192;
+const HANDSHAKE_TIMEOUT: u64 = 5;
+// producers get a fresh token every time their pipeline is built and only present it when connecting
+const PRODUCER_TOKEN_TTL: u64 = 300;
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+#[derive(Clone, Debug)]
+struct Grant {
+    producer: bool,
+    streams: Option<HashSet<String>>
+}
+
+impl Grant {
+    fn permits(&self, stream_id: Option<&String>) -> bool {
+        match (&self.streams, stream_id) {
+            (None, _) => true,
+            (Some(streams), Some(id)) => streams.contains(id),
+            (Some(_), None) => false,
+        }
+    }
+}
+
+fn token_claims(role: &str, expiry: u64, streams: &str) -> String {
+    format!("signalling|{}|{}|{}", role, expiry, streams)
+}
+
+// streams is a comma separated list of stream IDs, or "*" for every stream
+pub fn sign_signalling_token(secret: &str, role: &str, expiry: u64, streams: &str) -> String {
+    let signature = sign(secret, token_claims(role, expiry, streams).as_bytes());
+    format!("{}.{}.{}.{}", role, expiry, streams, signature)
+}
+
+fn verify_signalling_token(secret: &str, token: &str) -> Option<Grant> {
+    let parts: Vec<&str> = token.split('.').collect();
+
+    if parts.len() != 4 {
+        return None;
+    }
+
+    let (role, streams) = (parts[0], parts[2]);
+    let expiry = parts[1].parse::<u64>().ok()?;
+
+    if expiry < now() || (role != "consumer" && role != "producer") {
+        return None;
+    }
+
+    let signature = hex::decode(parts[3]).ok()?;
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).ok()?;
+    mac.update(token_claims(role, expiry, streams).as_bytes());
+    mac.verify_slice(&signature).ok()?;
+
+    Some(Grant {
+        producer: role == "producer",
+        streams: if streams == "*" { None } else { Some(streams.split(',').map(|s| s.to_string()).collect()) }
+    })
+}
+
+// an in-process consumer, such as a WHEP session, whose messages are relayed instead of going over a websocket
+struct Relay {
+    events: UnboundedSender<Value>,
+    stream_id: String,
+    // media section IDs of the consumer's offer, in order
+    mids: Vec<String>
+}
+
+static RELAY_IDS: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Default)]
+struct GateState {
+    grants: HashMap<String, Grant>,
+    producers: HashMap<String, String>,
+    sessions: HashMap<String, (String, String)>,
+    max_viewers: HashMap<String, usize>,
+    // messages from peers whose grant is not registered yet
+    pending: HashMap<String, Vec<IncomingMessage>>,
+    // peers whose held messages were replayed through `injected`, which the rest of their messages then follow
+    replayed: HashSet<String>,
+    relays: HashMap<String, Relay>
+}
+
+impl GateState {
+    fn forget(&mut self, peer_id: &str) {
+        self.grants.remove(peer_id);
+        self.producers.remove(peer_id);
+        self.sessions.retain(|_, (consumer, producer)| consumer != peer_id && producer != peer_id);
+        self.pending.remove(peer_id);
+        self.relays.remove(peer_id);
+    }
+}
+
+pub struct SignallingGate {
+    secret: Option<String>,
+    state: Mutex<GateState>,
+    replies: UnboundedSender<(String, OutgoingMessage)>,
+    demands: UnboundedSender<String>,
+    // messages fed to the handler as if a peer had sent them
+    injected: UnboundedSender<(String, Option<IncomingMessage>)>
+}
+
+impl SignallingGate {
+    fn register(&self, peer_id: &str, grant: Grant) {
+        if let Ok(mut state) = self.state.lock() {
+            state.grants.insert(peer_id.to_string(), grant);
+
+            if let Some(pending) = state.pending.remove(peer_id) {
+                state.replayed.insert(peer_id.to_string());
+
+                for message in pending {
+                    if let Some(message) = self.check_incoming(&mut state, peer_id, message) {
+                        let _ = self.injected.unbounded_send((peer_id.to_string(), Some(message)));
+                    }
+                }
+            }
+        }
+    }
+
+    fn grant(state: &GateState, secret: &Option<String>, peer_id: &str) -> Option<Grant> {
+        if secret.is_none() {
+            return Some(Grant { producer: true, streams: None });
+        }
+        state.grants.get(peer_id).cloned()
+    }
+
+    pub fn set_max_viewers(&self, stream_id: &str, max_viewers: Option<usize>) {
+        if let Ok(mut state) = self.state.lock() {
+            match max_viewers {
+                Some(max) => state.max_viewers.insert(stream_id.to_string(), max),
+                None => state.max_viewers.remove(stream_id),
+            };
+        }
+    }
+
+    // (session ID, consumer peer ID) for every session watching the stream
+    pub fn stream_sessions(&self, stream_id: &str) -> Vec<(String, String)> {
+        match self.state.lock() {
+            Ok(state) => state.sessions
+                .iter()
+                .filter(|(_, (_, producer))| state.producers.get(producer).map(|id| id == stream_id).unwrap_or(false))
+                .map(|(session_id, (consumer, _))| (session_id.clone(), consumer.clone()))
+                .collect(),
+            Err(_) => vec![],
+        }
+    }
+
+    // ends a consumer's sessions on the stream as if it had hung up, so the producer drops it too
+    pub fn end_sessions(&self, stream_id: &str, peer_id: &str) -> bool {
+        let sessions: Vec<String> = self.stream_sessions(stream_id)
+            .into_iter()
+            .filter(|(_, consumer)| consumer == peer_id)
+            .map(|(session_id, _)| session_id)
+            .collect();
+
+        let state = match self.state.lock() {
+            Ok(state) => state,
+            Err(_) => return false,
+        };
+
+        for session_id in &sessions {
+            let _ = self.injected.unbounded_send((peer_id.to_string(), Some(IncomingMessage::EndSession(EndSessionMessage {
+                session_id: session_id.clone(),
+            }))));
+            self.send(&state, peer_id, OutgoingMessage::EndSession(EndSessionMessage {
+                session_id: session_id.clone(),
+            }));
+        }
+
+        !sessions.is_empty()
+    }
+
+    // messages for relayed peers go to their in-process handler, everything else to the websocket
+    fn send(&self, state: &GateState, peer_id: &str, message: OutgoingMessage) {
+        match state.relays.get(peer_id) {
+            Some(relay) => {
+                if let Ok(value) = serde_json::to_value(&message) {
+                    let _ = relay.events.unbounded_send(value);
+                }
+            }
+            None => {
+                let _ = self.replies.unbounded_send((peer_id.to_string(), message));
+            }
+        }
+    }
+
+    fn reject(&self, state: &GateState, peer_id: &str, details: &str) {
+        self.send(state, peer_id, OutgoingMessage::Error {
+            details: details.to_string(),
+        });
+    }
+
+    // joins the server as a consumer of the stream; the handler's messages for it arrive on the receiver
+    pub fn open_relay(&self, stream_id: &str, mids: Vec<String>) -> Option<(String, UnboundedReceiver<Value>)> {
+        let peer_id = format!("relay-{}", RELAY_IDS.fetch_add(1, Ordering::Relaxed));
+        let (events, receiver) = unbounded();
+
+        let mut state = self.state.lock().ok()?;
+        state.grants.insert(peer_id.clone(), Grant { producer: false, streams: Some(HashSet::from([stream_id.to_string()])) });
+        state.relays.insert(peer_id.clone(), Relay { events, stream_id: stream_id.to_string(), mids });
+        drop(state);
+
+        let new_peer = serde_json::from_value::<IncomingMessage>(json!({ "type": "newPeer" })).ok()?;
+        self.injected.unbounded_send((peer_id.clone(), Some(new_peer))).ok()?;
+
+        Some((peer_id, receiver))
+    }
+
+    // a message from a relayed peer, in the signalling protocol's JSON form; false if it was malformed or refused
+    pub fn relay(&self, peer_id: &str, message: Value) -> bool {
+        let message = match serde_json::from_value::<IncomingMessage>(message) {
+            Ok(message) => message,
+            Err(_) => return false,
+        };
+
+        let mut state = match self.state.lock() {
+            Ok(state) => state,
+            Err(_) => return false,
+        };
+
+        if !state.relays.contains_key(peer_id) {
+            return false;
+        }
+
+        match self.check_incoming(&mut state, peer_id, message) {
+            Some(message) => self.injected.unbounded_send((peer_id.to_string(), Some(message))).is_ok(),
+            None => false,
+        }
+    }
+
+    // hangs up a relayed peer, ending its sessions with the producer
+    pub fn close_relay(&self, peer_id: &str) {
+        if let Ok(mut state) = self.state.lock() {
+            if state.relays.contains_key(peer_id) {
+                state.forget(peer_id);
+                let _ = self.injected.unbounded_send((peer_id.to_string(), None));
+            }
+        }
+    }
+
+    // the relayed peer holding a session on the stream, with its offer's media section IDs
+    pub fn relay_session(&self, stream_id: &str, session_id: &str) -> Option<(String, Vec<String>)> {
+        let state = self.state.lock().ok()?;
+        let (consumer, _) = state.sessions.get(session_id)?;
+        let relay = state.relays.get(consumer).filter(|relay| relay.stream_id == stream_id)?;
+
+        Some((consumer.clone(), relay.mids.clone()))
+    }
+
+    pub fn stream_producer(&self, stream_id: &str) -> Option<String> {
+        let state = self.state.lock().ok()?;
+
+        state.producers.iter().find(|(_, id)| id.as_str() == stream_id).map(|(peer_id, _)| peer_id.clone())
+    }
+
+    fn filter_incoming(&self, peer_id: String, message: Option<IncomingMessage>) -> Option<(String, Option<IncomingMessage>)> {
+        let mut state = self.state.lock().ok()?;
+
+        let message = match message {
+            Some(message) => message,
+            None => {
+                state.forget(&peer_id);
+
+                if state.replayed.remove(&peer_id) {
+                    let _ = self.injected.unbounded_send((peer_id, None));
+                    return None;
+                }
+                return Some((peer_id, None));
+            }
+        };
+
+        // the server starts reading a connection before accept_async hands back its peer ID, so
+        // anything sent before the token's grant is registered waits for it
+        if self.secret.is_some() && !state.grants.contains_key(&peer_id) {
+            state.pending.entry(peer_id).or_default().push(message);
+            return None;
+        }
+
+        let message = self.check_incoming(&mut state, &peer_id, message)?;
+
+        if state.replayed.contains(&peer_id) {
+            let _ = self.injected.unbounded_send((peer_id, Some(message)));
+            return None;
+        }
+
+        Some((peer_id, Some(message)))
+    }
+
+    fn check_incoming(&self, state: &mut GateState, peer_id: &str, message: IncomingMessage) -> Option<IncomingMessage> {
+        let grant = Self::grant(state, &self.secret, peer_id);
+
+        match &message {
+            IncomingMessage::SetPeerStatus(status) if status.roles.iter().any(|r| matches!(r, PeerRole::Producer)) => {
+                let stream_id = status.meta.as_ref()
+                    .and_then(|meta| meta.get("stream-id"))
+                    .and_then(|id| id.as_str())
+                    .map(|id| id.to_string());
+
+                if !grant.map(|g| g.producer && g.permits(stream_id.as_ref())).unwrap_or(false) {
+                    self.reject(state, peer_id, "Not permitted to produce");
+                    return None;
+                }
+
+                if let Some(stream_id) = stream_id {
+                    state.producers.insert(peer_id.to_string(), stream_id);
+                }
+            }
+            IncomingMessage::StartSession(start) => {
+                let permitted = grant.map(|g| g.permits(state.producers.get(&start.peer_id))).unwrap_or(false);
+
+                if !permitted {
+                    self.reject(state, peer_id, "Not permitted to watch this stream");
+                    return None;
+                }
+
+                let limit = state.producers.get(&start.peer_id).and_then(|stream_id| state.max_viewers.get(stream_id)).copied();
+
+                if let Some(limit) = limit {
+                    let watching = state.sessions.values().filter(|(_, producer)| producer == &start.peer_id).count();
+
+                    if watching >= limit {
+                        self.reject(state, peer_id, "Stream has reached its viewer limit");
+                        return None;
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        Some(message)
+    }
+
+    fn filter_outgoing(&self, peer_id: String, message: OutgoingMessage) -> Option<(String, OutgoingMessage)> {
+        let mut state = self.state.lock().ok()?;
+        let grant = Self::grant(&state, &self.secret, &peer_id);
+
+        let message = match message {
+            OutgoingMessage::List { producers } => {
+                let grant = grant?;
+                let producers = producers
+                    .into_iter()
+                    .filter(|producer| grant.permits(state.producers.get(&producer.id)))
+                    .collect();
+
+                OutgoingMessage::List { producers }
+            }
+            OutgoingMessage::PeerStatusChanged(status) => {
+                let producer = status.peer_id.as_ref().and_then(|id| state.producers.get(id));
+
+                if status.roles.iter().any(|r| matches!(r, PeerRole::Producer)) && !grant?.permits(producer) {
+                    return None;
+                }
+
+                OutgoingMessage::PeerStatusChanged(status)
+            }
+            OutgoingMessage::SessionStarted { peer_id: producer_id, session_id } => {
+                state.sessions.insert(session_id.clone(), (peer_id.clone(), producer_id.clone()));
+
+                OutgoingMessage::SessionStarted { peer_id: producer_id, session_id }
+            }
+            OutgoingMessage::EndSession(end) => {
+                state.sessions.remove(&end.session_id);
+
+                OutgoingMessage::EndSession(end)
+            }
+            message => message,
+        };
+
+        if state.relays.contains_key(&peer_id) {
+            self.send(&state, &peer_id, message);
+            return None;
+        }
+
+        Some((peer_id, message))
+    }
+}
+
+// the receiver yields the stream IDs consumers ask for with `?stream=`, so on-demand streams can be woken
+pub fn spawn_server(secret: Option<String>) -> (Server, Arc<SignallingGate>, UnboundedReceiver<String>) {
+    let (replies, replies_rx) = unbounded::<(String, OutgoingMessage)>();
+    let (demands, demands_rx) = unbounded::<String>();
+    let (injected, injected_rx) = unbounded::<(String, Option<IncomingMessage>)>();
+
+    let gate = Arc::new(SignallingGate {
+        secret,
+        state: Mutex::new(GateState::default()),
+        replies,
+        demands,
+        injected
+    });
+
+    let incoming_gate = gate.clone();
+    let outgoing_gate = gate.clone();
+
+    let server = Server::spawn(move |incoming| {
+        let incoming = incoming.filter_map(move |(peer_id, message)| {
+            futures::future::ready(incoming_gate.filter_incoming(peer_id, message))
+        });
+
+        let incoming = futures::stream::select(incoming, injected_rx);
+
+        let outgoing = Handler::new(Box::pin(incoming)).filter_map(move |(peer_id, message)| {
+            futures::future::ready(outgoing_gate.filter_outgoing(peer_id, message))
+        });
+
+        futures::stream::select(outgoing, replies_rx)
+    });
+
+    (server, gate, demands_rx)
+}
+
+// replays the bytes consumed while reading the handshake before handing the stream to the signalling server
+pub struct PrefixedStream<S> {
+    prefix: Vec<u8>,
+    offset: usize,
+    inner: S
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for PrefixedStream<S> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        if this.offset < this.prefix.len() {
+            let remaining = &this.prefix[this.offset..];
+            let n = remaining.len().min(buf.remaining());
+            buf.put_slice(&remaining[..n]);
+            this.offset += n;
+            return Poll::Ready(Ok(()));
+        }
+
+        Pin::new(&mut this.inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for PrefixedStream<S> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+async fn read_request_head<S: AsyncRead + Unpin>(stream: &mut S) -> Result<Vec<u8>, String> {
+    let mut head = Vec::with_capacity(1024);
+    let mut chunk = [0u8; 1024];
+
+    loop {
+        let n = stream.read(&mut chunk).await.map_err(|e| e.to_string())?;
+
+        if n == 0 {
+            return Err(String::from("Connection closed during handshake"));
+        }
+
+        head.extend_from_slice(&chunk[..n]);
+
+        if head.windows(4).any(|w| w == b"\r\n\r\n") {
+            return Ok(head);
+        }
+
+        if head.len() > MAX_HANDSHAKE {
+            return Err(String::from("Handshake too large"));
+        }
+    }
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok().and_then(|h| u8::from_str_radix(h, 16).ok());
+
+            if let Some(byte) = hex {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+pub fn query_param(head: &[u8], name: &str) -> Option<String> {
+    let head = String::from_utf8_lossy(head);
+    let target = head.lines().next()?.split_whitespace().nth(1)?;
+    let query = target.split_once('?')?.1;
+
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| percent_decode(value))
+}
+
+pub async fn accept_connection<S>(mut server: Server, gate: Arc<SignallingGate>, mut stream: S) -> Result<String, String>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let head = tokio::time::timeout(Duration::from_secs(HANDSHAKE_TIMEOUT), read_request_head(&mut stream))
+        .await
+        .map_err(|_| String::from("Handshake timed out"))??;
+
+    let grant = match &gate.secret {
+        Some(secret) => match query_param(&head, "token").and_then(|token| verify_signalling_token(secret, &token)) {
+            Some(grant) => Some(grant),
+            None => {
+                let _ = stream.write_all(b"HTTP/1.1 401 Unauthorized\r\nContent-Length: 0\r\nConnection: close\r\n\r\n").await;
+                return Err(String::from("Rejected signalling connection without a valid token"));
+            }
+        },
+        None => None,
+    };
+
+    // a consumer naming the stream it wants starts it if it is on-demand, before any producer exists to find
+    if let Some(stream_id) = query_param(&head, "stream") {
+        if grant.as_ref().map(|g| g.permits(Some(&stream_id))).unwrap_or(true) {
+            let _ = gate.demands.unbounded_send(stream_id);
+        }
+    }
+
+    let prefixed = PrefixedStream { prefix: head, offset: 0, inner: stream };
+    let peer_id = server.accept_async(prefixed).await.map_err(|e| e.to_string())?;
+
+    if let Some(grant) = grant {
+        gate.register(&peer_id, grant);
+    }
+
+    Ok(peer_id)
+}
+
+pub async fn run_signalling(listener: TcpListener, server: Server, gate: Arc<SignallingGate>) {
+    while let Ok((stream, _address)) = listener.accept().await {
+        let server_clone = server.clone();
+        let gate_clone = gate.clone();
+
+        task::spawn(async move {
+            if let Err(e) = accept_connection(server_clone, gate_clone, stream).await {
+                eprintln!("Error accepting connection: {}", e);
+            }
+        });
+    }
+}
+
+pub async fn run_signalling_tls(listener: TcpListener, server: Server, gate: Arc<SignallingGate>, acceptor: Arc<RwLock<TlsAcceptor>>) {
+    while let Ok((stream, _address)) = listener.accept().await {
+        let server_clone = server.clone();
+        let gate_clone = gate.clone();
+        let acceptor = match acceptor.read() {
+            Ok(acceptor) => acceptor.clone(),
+            Err(_) => continue,
+        };
+
+        task::spawn(async move {
+            let stream = match acceptor.accept(stream).await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    eprintln!("TLS handshake failed: {}", e);
+                    return;
+                }
+            };
+
+            if let Err(e) = accept_connection(server_clone, gate_clone, stream).await {
+                eprintln!("Error accepting connection: {}", e);
+            }
+        });
+    }
+}
+
+// signaller URI for the webrtcsink producers started by this server, which always use the plain loopback listener
+pub fn producer_signaller_uri(config: &Config, stream_id: &str) -> String {
+    match &config.signalling.secret {
+        Some(secret) => {
+            let token = sign_signalling_token(secret, "producer", now() + PRODUCER_TOKEN_TTL, stream_id);
+            format!("ws://{}?token={}", config.listen.signalling, token)
+        }
+        None => format!("ws://{}", config.listen.signalling),
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct SignallingToken {
+    pub token: Option<String>,
+    pub expires: Option<u64>
+}
+
+#[utoipa::path(
+    get,
+    path = "/streams/{id}/webrtc-token",
+    tag = "playback",
+    params(("id" = String, Path, description = "Stream ID")),
+    responses(
+        (status = 200, description = "Signalling token for the stream", body = ResponseData<SignallingToken>)
+    )
+)]
+pub async fn get_webrtc_token(Path(id): Path<String>, State(state): State<AppState>) -> Json<ResponseData<SignallingToken>> {
+    let streams_lock = state.streams.lock().await;
+    let exists = streams_lock.values().any(|s| s.id == id && s.stream_type != "HLS");
+    drop(streams_lock);
+
+    if !exists {
+        return Json(ResponseData {
+            status: false,
+            message: String::from("Stream not found"),
+            data: None
+        });
+    }
+
+    let data = match &state.config.signalling.secret {
+        Some(secret) => {
+            let expiry = now() + state.config.signalling.token_ttl;
+            SignallingToken { token: Some(sign_signalling_token(secret, "consumer", expiry, &id)), expires: Some(expiry) }
+        }
+        None => SignallingToken { token: None, expires: None },
+    };
+
+    Json(ResponseData {
+        status: true,
+        message: String::from("Fetch Success"),
+        data: Some(data)
+    })
+}
@@ -2,100 +2,814 @@
 
 use std::collections::HashMap;
 use std::sync::Arc;
-use axum::extract::{Query, State};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use axum::extract::{Path, Query, State};
+use axum::Extension;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
 use axum::Json;
-use gstreamer::prelude::{ElementExt, ElementExtManual};
+use futures::StreamExt;
+use gstreamer::prelude::{Cast, ElementExt, ElementExtManual, ObjectExt};
 use gstreamer_app::gst;
+use serde::{Serialize, Serializer};
+use sha2::{Digest, Sha256};
 use tokio::sync::Mutex;
-use crate::structures::{EncodeOptions, ResponseData, RtspResponse, RtspStream, StreamData};
-use crate::streamer::{create_and_manage_playlists, create_hls_pipeline, create_webrtc_pipeline};
+use crate::api::{respond, select_streams};
+use crate::openapi::ErrorResponse;
+use crate::auth::Principal;
+use crate::demand::IDLE_MESSAGE;
+use crate::limits::{check_capacity, transcode_cost};
+use crate::selector::{validate_metadata, with_total, StreamQuery};
+use crate::signalling::producer_signaller_uri;
+use crate::structures::{AppState, EncodeOptions, ResponseData, RtspResponse, RtspStream, StreamData, StreamEvent, StreamEventKind, StreamInfo, StreamPatch, StreamState, StreamStats, validate_video_bitrate, WebRtcOptions};
+use crate::validation::{reject_source, validate_source};
+use crate::viewers::{track_viewers, ViewerRegistry};
+use crate::playlist::{segment_index, HlsPlaylists};
+use crate::streamer::{apply_video_bitrate, create_and_manage_playlists, create_hls_pipeline, create_webrtc_pipeline, default_hls_options, find_elements, MEDIA_MESSAGE, pipeline_lost_packets, pipeline_output_caps};
 
-pub async fn add_stream(State(streams): State<Arc<Mutex<HashMap<String, RtspStream>>>>, Json(payload): Json<StreamData>) -> Json<ResponseData> {
+const RESTART_MESSAGE: &str = "rtsp-restart";
+
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+pub fn stream_id(rtsp: &str, stream_type: &str) -> String {
+    let digest = Sha256::digest(format!("{}-{}", rtsp, stream_type).as_bytes());
+    hex::encode(&digest[..8])
+}
+
+pub fn redact_rtsp(rtsp: &str) -> String {
+    if let Some(scheme_end) = rtsp.find("://") {
+        let rest = &rtsp[scheme_end + 3..];
+        let authority_end = rest.find('/').unwrap_or(rest.len());
+
+        if let Some(at) = rest[..authority_end].rfind('@') {
+            let user = rest[..at].split(':').next().unwrap_or("");
+            return format!("{}://{}:***@{}", &rtsp[..scheme_end], user, &rest[at + 1..]);
+        }
+    }
+
+    rtsp.to_string()
+}
+
+async fn update_stream<F: FnOnce(&mut RtspStream)>(state: &AppState, key: &str, generation: u64, update: F) -> bool {
+    let mut streams_lock = state.streams.lock().await;
+
+    match streams_lock.get_mut(key) {
+        Some(stream) if stream.generation == generation => {
+            let previous = stream.state;
+            update(stream);
+
+            if stream.state != previous {
+                let _ = state.events.send(StreamEvent::new(&stream.id, StreamEventKind::StateChanged {
+                    state: stream.state,
+                    message: stream.message.clone(),
+                }));
+            }
+            true
+        }
+        _ => false
+    }
+}
+
+async fn supervise_stream(state: AppState, key: String, generation: u64) {
+    let reconnect = &state.config.reconnect;
+    let mut delay = reconnect.delay;
+
+    loop {
+        let streams_lock = state.streams.lock().await;
+        let mut stream = match streams_lock.get(key.as_str()) {
+            Some(stream) if stream.generation == generation => stream.clone(),
+            _ => return,
+        };
+        drop(streams_lock);
+
+        // on-demand streams wait here until a viewer asks for them
+        if stream.on_demand {
+            let wake = stream.wake.clone();
+            let notified = wake.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+
+            // demand is checked under the same lock touch_stream takes, so a viewer either shows up here or finds the stream Idle and wakes it
+            let mut demanded = false;
+
+            let registered = update_stream(&state, &key, generation, |s| {
+                demanded = s.last_demand.map(|at| at.elapsed().as_secs() < state.config.on_demand.idle_timeout).unwrap_or(false);
+
+                if !demanded {
+                    s.state = StreamState::Idle;
+                    s.status = false;
+                    s.message = String::from("Waiting for a viewer");
+                    s.pipeline = None;
+                    s.started_at = None;
+                }
+            }).await;
+
+            if !registered {
+                return;
+            }
+
+            if !demanded {
+                notified.await;
+                delay = reconnect.delay;
+                continue;
+            }
+        }
+
+        // held until media flows from the source, so cameras and encoders aren't all brought up at once
+        let mut permit = match state.scheduler.try_acquire(&stream.rtsp) {
+            Some(permit) => Some(permit),
+            None => {
+                let queued = update_stream(&state, &key, generation, |s| {
+                    s.state = StreamState::Queued;
+                    s.status = false;
+                    s.message = String::from("Waiting for a start slot");
+                }).await;
+
+                if !queued {
+                    return;
+                }
+
+                let permit = state.scheduler.acquire(&stream.id, &stream.rtsp, stream.priority).await;
+
+                // it may have been changed, restarted or deleted while it waited
+                let streams_lock = state.streams.lock().await;
+                stream = match streams_lock.get(key.as_str()) {
+                    Some(stream) if stream.generation == generation => stream.clone(),
+                    _ => return,
+                };
+                drop(streams_lock);
+
+                Some(permit)
+            }
+        };
+
+        let created = if stream.stream_type == "HLS" {
+            let (fresh, start_index) = match stream.playlists.lock() {
+                Ok(mut playlists) => {
+                    let fresh = !playlists.is_started();
+
+                    if fresh {
+                        playlists.reset();
+                    } else {
+                        playlists.mark_discontinuity();
+                    }
+
+                    (fresh, playlists.next_index())
+                }
+                Err(_) => (true, 0),
+            };
+
+            create_and_manage_playlists(stream.rtsp.as_str(), stream.id.as_str(), stream.encode_options.clone(), stream.video_bitrate, fresh)
+                .and_then(|(output_dir, qualities)| {
+                    if let Ok(mut playlists) = stream.playlists.lock() {
+                        playlists.prune();
+                    }
+
+                    create_hls_pipeline(stream.rtsp.as_str(), &output_dir, qualities, stream.encode_options.clone(), stream.hls_options.clone(), stream.stats.clone(), start_index)
+                })
+        } else {
+            let webrtc_options = WebRtcOptions {
+                ice: Some(stream.webrtc_options.ice.clone().unwrap_or_default().merged(&state.config.ice)),
+                ..stream.webrtc_options.clone()
+            };
+
+            create_webrtc_pipeline(stream.rtsp.as_str(), stream.id.as_str(), stream.encode_options.clone(), stream.video_bitrate, &webrtc_options, stream.stats.clone(), producer_signaller_uri(&state.config, &stream.id))
+        };
+
+        let pipeline = match created {
+            Ok(pipeline) => pipeline,
+            Err(e) => {
+                update_stream(&state, &key, generation, |s| {
+                    s.state = StreamState::Failed;
+                    s.status = false;
+                    s.message = e;
+                    s.pipeline = None;
+                }).await;
+                return;
+            }
+        };
+
+        track_viewers(&pipeline, stream.viewers.clone());
+
+        let bus = match pipeline.bus() {
+            Some(bus) => bus,
+            None => {
+                update_stream(&state, &key, generation, |s| {
+                    s.state = StreamState::Failed;
+                    s.status = false;
+                    s.message = String::from("Bus not initialized");
+                    s.pipeline = None;
+                }).await;
+                return;
+            }
+        };
+        let mut messages = bus.stream();
+
+        // like the jitterbuffer counts, dropped frames are reported for the current pipeline only
+        if let Ok(mut stats) = stream.stats.lock() {
+            stats.clear_dropped();
+        }
+
+        let started = Instant::now();
+        let mut failure = None;
+        let mut restart = false;
+        let mut idle = false;
+
+        // a paused stream that gets rebuilt stays paused
+        let target = if stream.paused { gst::State::Paused } else { gst::State::Playing };
+
+        if pipeline.set_state(target).is_err() {
+            failure = Some(String::from("Failed to start pipeline"));
+        } else {
+            let mut paused = stream.paused;
+
+            // it only counts as running once the source delivers media, see MEDIA_MESSAGE below
+            let registered = update_stream(&state, &key, generation, |s| {
+                paused = s.paused;
+                s.state = if s.paused { StreamState::Paused } else { StreamState::Starting };
+                s.status = false;
+                s.message = String::from(if s.paused { "Paused" } else { "Waiting for media" });
+                s.pipeline = Some(pipeline.clone());
+                s.started_at = Some(started);
+            }).await;
+
+            if !registered {
+                let _ = pipeline.set_state(gst::State::Null);
+                return;
+            }
+
+            if paused != stream.paused {
+                let _ = pipeline.set_state(if paused { gst::State::Paused } else { gst::State::Playing });
+            }
+
+            // a paused pipeline doesn't pull media, so don't hold a slot for it
+            if paused {
+                permit = None;
+            }
+
+            let start_deadline = tokio::time::Instant::now() + Duration::from_secs(state.config.startup.start_timeout);
+
+            loop {
+                let next = if permit.is_some() {
+                    match tokio::time::timeout_at(start_deadline, messages.next()).await {
+                        Ok(next) => next,
+                        Err(_) => {
+                            permit = None;
+                            continue;
+                        }
+                    }
+                } else {
+                    messages.next().await
+                };
+
+                let msg = match next {
+                    Some(msg) => msg,
+                    None => break,
+                };
+
+                match msg.view() {
+                    gst::MessageView::Eos(..) => break,
+                    gst::MessageView::Application(application) => {
+                        if application.structure().map(|s| s.has_name(MEDIA_MESSAGE)).unwrap_or(false) {
+                            permit = None;
+
+                            update_stream(&state, &key, generation, |s| {
+                                if s.state == StreamState::Starting {
+                                    s.state = StreamState::Running;
+                                    s.status = true;
+                                    s.message = String::from("Started");
+                                }
+                            }).await;
+                        }
+
+                        if application.structure().map(|s| s.has_name(RESTART_MESSAGE)).unwrap_or(false) {
+                            restart = true;
+                            break;
+                        }
+
+                        if application.structure().map(|s| s.has_name(IDLE_MESSAGE)).unwrap_or(false) {
+                            idle = true;
+                            break;
+                        }
+                    }
+                    gst::MessageView::Error(err) => {
+                        failure = Some(err.error().to_string());
+                        let _ = state.events.send(StreamEvent::new(&stream.id, StreamEventKind::Error {
+                            message: err.error().to_string(),
+                        }));
+                        break;
+                    }
+                    gst::MessageView::Warning(warning) => {
+                        let _ = state.events.send(StreamEvent::new(&stream.id, StreamEventKind::Warning {
+                            message: warning.error().to_string(),
+                        }));
+                    }
+                    gst::MessageView::Qos(qos) => {
+                        let (_, dropped) = qos.stats();
+
+                        if let (Some(src), Ok(dropped)) = (qos.src(), u64::try_from(dropped.value())) {
+                            if let Ok(mut stats) = stream.stats.lock() {
+                                stats.record_dropped(src.path_string().to_string(), dropped);
+                            }
+                        }
+                    }
+                    gst::MessageView::Element(element) => {
+                        if let Some(structure) = element.structure() {
+                            if structure.has_name("hls-segment-added") {
+                                let start = structure.get::<gst::ClockTime>("running-time");
+                                let duration = structure.get::<gst::ClockTime>("duration");
+
+                                if let (Ok(location), Ok(duration)) = (structure.get::<String>("location"), duration.as_ref()) {
+                                    if let Ok(mut playlists) = stream.playlists.lock() {
+                                        let seconds = duration.nseconds() as f64 / 1_000_000_000.0;
+                                        playlists.add_segment(&location, seconds, segment_index(&location));
+                                    }
+                                }
+
+                                if let (Ok(start), Ok(duration), Some(now)) = (start, duration, pipeline.current_running_time()) {
+                                    let latency = now.saturating_sub(start + duration);
+                                    state.metrics.observe_segment_latency(&stream.id, latency.nseconds() as f64 / 1_000_000_000.0);
+                                }
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        drop(permit);
+        let _ = pipeline.set_state(gst::State::Null);
+
+        if let Ok(mut viewers) = stream.viewers.lock() {
+            viewers.clear();
+        }
+
+        // the next viewer gets a fresh playlist rather than the tail of the last session
+        if idle {
+            if let Ok(mut playlists) = stream.playlists.lock() {
+                playlists.reset();
+            }
+
+            delay = reconnect.delay;
+            continue;
+        }
+
+        if restart {
+            let restarting = update_stream(&state, &key, generation, |s| {
+                s.state = StreamState::Starting;
+                s.status = false;
+                s.message = String::from("Restarting");
+                s.pipeline = None;
+                s.started_at = None;
+            }).await;
+
+            if !restarting {
+                return;
+            }
+
+            delay = reconnect.delay;
+            continue;
+        }
+
+        let error = match failure {
+            Some(error) => error,
+            None => {
+                update_stream(&state, &key, generation, |s| {
+                    s.state = StreamState::Stopped;
+                    s.status = false;
+                    s.message = String::from("Pipeline Ended");
+                    s.pipeline = None;
+                    s.started_at = None;
+                }).await;
+                return;
+            }
+        };
+
+        eprintln!("Stream '{}' failed: {}", redact_rtsp(&stream.rtsp), error);
+
+        if !reconnect.enabled {
+            update_stream(&state, &key, generation, |s| {
+                s.state = StreamState::Failed;
+                s.status = false;
+                s.message = error;
+                s.pipeline = None;
+                s.started_at = None;
+            }).await;
+            return;
+        }
+
+        let reconnecting = update_stream(&state, &key, generation, |s| {
+            s.state = StreamState::Reconnecting;
+            s.status = false;
+            s.message = format!("Reconnecting: {}", error);
+            s.pipeline = None;
+            s.started_at = None;
+            s.reconnects += 1;
+        }).await;
+
+        if !reconnecting {
+            return;
+        }
+
+        if started.elapsed().as_secs() >= reconnect.stable_run {
+            delay = reconnect.delay;
+        }
+
+        let _ = state.events.send(StreamEvent::new(&stream.id, StreamEventKind::Reconnecting {
+            attempt: stream.reconnects + 1,
+            delay,
+        }));
+
+        tokio::time::sleep(Duration::from_secs(delay)).await;
+        delay = (delay * 2).min(reconnect.max_delay);
+    }
+}
+
+pub fn stream_info(state: &AppState, stream: &RtspStream) -> StreamInfo {
+    let (input_caps, bitrate, fps, dropped_frames) = match stream.stats.lock() {
+        Ok(stats) => {
+            let (bitrate, fps) = stats.rates();
+            (stats.input_caps.clone(), bitrate, fps, stats.dropped_frames())
+        }
+        Err(_) => (None, 0, 0.0, 0),
+    };
+
+    let (output_caps, lost_packets) = match &stream.pipeline {
+        Some(pipeline) => (pipeline_output_caps(pipeline), pipeline_lost_packets(pipeline)),
+        None => (vec![], 0),
+    };
+
+    // counted from the playlist state rather than the output directory, this runs in async handlers
+    let hls_segments = match (&stream.pipeline, stream.stream_type.as_str()) {
+        (Some(_), "HLS") => stream.playlists.lock().ok().map(|playlists| playlists.segment_count()),
+        _ => None,
+    };
+
+    StreamInfo {
+        id: stream.id.clone(),
+        name: stream.name.clone(),
+        group: stream.group.clone(),
+        labels: stream.labels.clone(),
+        source: redact_rtsp(&stream.rtsp),
+        stream_type: stream.stream_type.clone(),
+        encode_options: stream.encode_options.clone(),
+        hls_options: stream.hls_options.clone(),
+        video_bitrate: stream.video_bitrate,
+        state: stream.state,
+        message: stream.message.clone(),
+        input_caps,
+        output_caps,
+        uptime: stream.started_at.map(|started| started.elapsed().as_secs()),
+        reconnects: stream.reconnects,
+        bitrate,
+        fps,
+        lost_packets,
+        dropped_frames,
+        hls_segments,
+        queue_position: state.scheduler.position(&stream.id),
+        priority: stream.priority,
+        on_demand: stream.on_demand,
+        viewers: stream.viewers.lock().map(|viewers| viewers.count()).unwrap_or(0),
+        max_viewers: stream.max_viewers,
+        hls_viewers: state.hls.viewers(&stream.id),
+        webrtc_options: stream.webrtc_options.redacted()
+    }
+}
+
+pub async fn find_stream(state: &AppState, id: &str) -> Option<RtspStream> {
+    let streams_lock = state.streams.lock().await;
+    streams_lock.values().find(|s| s.id == id).cloned()
+}
+
+// validates, checks limits and spawns the supervisor; an existing stream for the same source is only replaced when `replace` is set
+pub async fn start_stream(state: &AppState, owner: &str, payload: StreamData, replace: bool) -> Result<RtspStream, Response> {
+    validate_source(&state.config.sources, &payload.rtsp).await.map_err(|e| reject_source::<()>(e).into_response())?;
+    validate_metadata(payload.name.as_ref(), payload.group.as_ref(), payload.labels.as_ref())
+        .map_err(|e| respond::<()>(StatusCode::UNPROCESSABLE_ENTITY, e, None))?;
+
+    if let Some(webrtc_options) = &payload.webrtc_options {
+        webrtc_options.validate_with(&state.config.ice).map_err(|e| respond::<()>(StatusCode::UNPROCESSABLE_ENTITY, e, None))?;
+    }
+
+    let id = stream_id(&payload.rtsp, &payload.stream_type);
     let formatted_encode_options = payload.encode_options.unwrap_or_else(|| EncodeOptions::NONE);
-    let qualities = create_and_manage_playlists(payload.rtsp.clone(),formatted_encode_options.clone());
 
-    let mut streams_lock = streams.lock().await;
+    validate_video_bitrate(&payload.stream_type, &formatted_encode_options, payload.video_bitrate)
+        .map_err(|e| respond::<()>(StatusCode::UNPROCESSABLE_ENTITY, e, None))?;
+
+    let mut streams_lock = state.streams.lock().await;
 
     let formatted_rtsp = format!("{}-{}",payload.rtsp.clone(),payload.stream_type.clone());
 
-    streams_lock.insert(formatted_rtsp.clone(), RtspStream{
+    if let Some(existing) = streams_lock.get(formatted_rtsp.as_str()) {
+        if !replace {
+            return Err(respond::<()>(StatusCode::CONFLICT, format!("Stream '{}' already exists", existing.id), None));
+        }
+    }
+
+    let cost = transcode_cost(&payload.stream_type, &formatted_encode_options);
+    check_capacity(&state.config.limits, &streams_lock, &formatted_rtsp, owner, cost).map_err(IntoResponse::into_response)?;
+
+    // the stream being replaced keeps running until its replacement has been accepted
+    if let Some(existing) = streams_lock.get(formatted_rtsp.as_str()) {
+        existing.wake.notify_waiters();
+
+        if let Some(pipeline) = &existing.pipeline {
+            pipeline.send_event(gst::event::Eos::new());
+        }
+    }
+
+    let generation = GENERATION.fetch_add(1, Ordering::SeqCst);
+    let playlist_options = payload.hls_options.clone().unwrap_or_else(default_hls_options);
+
+    let stream = RtspStream{
+        id: id.clone(),
+        rtsp: payload.rtsp.clone(),
+        stream_type: payload.stream_type.clone(),
+        encode_options: formatted_encode_options,
+        hls_options: payload.hls_options,
+        video_bitrate: payload.video_bitrate,
+        state: StreamState::Starting,
         status: false,
         message: String::from("Starting"),
-        pipeline: None
-    });
+        pipeline: None,
+        started_at: None,
+        reconnects: 0,
+        generation,
+        stats: Arc::new(std::sync::Mutex::new(StreamStats::default())),
+        owner: owner.to_string(),
+        playlists: Arc::new(std::sync::Mutex::new(HlsPlaylists::new(playlist_options.max_files, playlist_options.duration))),
+        paused: false,
+        priority: payload.priority.unwrap_or(0),
+        name: payload.name,
+        group: payload.group,
+        labels: payload.labels.unwrap_or_default(),
+        on_demand: payload.on_demand.unwrap_or(false),
+        last_demand: None,
+        wake: Arc::new(tokio::sync::Notify::new()),
+        viewers: Arc::new(std::sync::Mutex::new(ViewerRegistry::default())),
+        max_viewers: payload.max_viewers,
+        webrtc_options: payload.webrtc_options.unwrap_or_default(),
+    };
+
+    state.signalling.set_max_viewers(&id, stream.max_viewers.map(|max| max as usize));
+    streams_lock.insert(formatted_rtsp.clone(), stream.clone());
 
     drop(streams_lock);
 
-    tokio::spawn(async move {
-        let create_pipeline = |stream_type: &str| -> Result<gst::Pipeline, String> {
-            if stream_type == "HLS" {
-                create_hls_pipeline(payload.rtsp.as_str(), qualities, formatted_encode_options, payload.hls_options)
-            } else {
-                create_webrtc_pipeline(payload.rtsp.as_str(),formatted_encode_options)
+    let _ = state.events.send(StreamEvent::new(&id, StreamEventKind::StateChanged {
+        state: StreamState::Starting,
+        message: String::from("Starting"),
+    }));
+
+    tokio::spawn(supervise_stream(state.clone(), formatted_rtsp, generation));
+
+    Ok(stream)
+}
+
+pub async fn stop_stream(state: &AppState, id: &str) -> Option<RtspStream> {
+    let mut streams_lock = state.streams.lock().await;
+
+    let key = streams_lock.iter().find(|(_, s)| s.id == id).map(|(key, _)| key.clone())?;
+    let stream = streams_lock.remove(key.as_str())?;
+
+    drop(streams_lock);
+
+    // an idle supervisor is waiting for a viewer, let it notice the stream is gone
+    stream.wake.notify_waiters();
+    state.signalling.set_max_viewers(&stream.id, None);
+
+    if let Some(pipeline) = &stream.pipeline {
+        pipeline.send_event(gst::event::Eos::new());
+    }
+
+    let _ = state.events.send(StreamEvent::new(&stream.id, StreamEventKind::Deleted));
+
+    Some(stream)
+}
+
+// rebuilds the pipeline from the stream's current configuration
+pub async fn reload_stream(state: &AppState, id: &str) -> Result<RtspStream, Response> {
+    let mut streams_lock = state.streams.lock().await;
+
+    let (key, stream) = match streams_lock.iter_mut().find(|(_, s)| s.id == id) {
+        Some((key, stream)) => (key.clone(), stream),
+        None => return Err(respond::<()>(StatusCode::NOT_FOUND, "Stream not found", None)),
+    };
+
+    match &stream.pipeline {
+        // the running supervisor tears the pipeline down and starts over
+        Some(pipeline) => {
+            if let Some(bus) = pipeline.bus() {
+                let _ = bus.post(gst::message::Application::new(gst::Structure::new_empty(RESTART_MESSAGE)));
             }
+        }
+        // nothing is running, so hand the stream to a fresh supervisor and let any pending one lapse
+        None => {
+            let generation = GENERATION.fetch_add(1, Ordering::SeqCst);
+            stream.generation = generation;
+            stream.state = StreamState::Starting;
+            stream.status = false;
+            stream.message = String::from("Restarting");
+            stream.wake.notify_waiters();
+
+            let _ = state.events.send(StreamEvent::new(&stream.id, StreamEventKind::StateChanged {
+                state: StreamState::Starting,
+                message: stream.message.clone(),
+            }));
+
+            tokio::spawn(supervise_stream(state.clone(), key, generation));
+        }
+    }
+
+    Ok(stream.clone())
+}
+
+// stops ingest but keeps the stream and its pipeline, so it can be resumed quickly
+pub async fn pause_ingest(state: &AppState, id: &str) -> Result<RtspStream, Response> {
+    let mut streams_lock = state.streams.lock().await;
+
+    let (key, stream) = match streams_lock.iter_mut().find(|(_, s)| s.id == id) {
+        Some((key, stream)) => (key.clone(), stream),
+        None => return Err(respond::<()>(StatusCode::NOT_FOUND, "Stream not found", None)),
+    };
+
+    if matches!(stream.state, StreamState::Stopped | StreamState::Failed) {
+        return Err(respond::<()>(StatusCode::CONFLICT, format!("Stream is {}", stream.state.as_str()), None));
+    }
+
+    stream.paused = true;
+    let generation = stream.generation;
+    let pipeline = stream.pipeline.clone();
+    drop(streams_lock);
+
+    if let Some(pipeline) = &pipeline {
+        if pipeline.set_state(gst::State::Paused).is_err() {
+            return Err(respond::<()>(StatusCode::INTERNAL_SERVER_ERROR, "Failed to pause pipeline", None));
+        }
+    }
+
+    update_stream(state, &key, generation, |s| {
+        s.state = StreamState::Paused;
+        s.status = false;
+        s.message = String::from("Paused");
+    }).await;
+
+    find_stream(state, id).await.ok_or_else(|| respond::<()>(StatusCode::NOT_FOUND, "Stream not found", None))
+}
+
+pub async fn resume_ingest(state: &AppState, id: &str) -> Result<RtspStream, Response> {
+    let mut streams_lock = state.streams.lock().await;
+
+    let (key, stream) = match streams_lock.iter_mut().find(|(_, s)| s.id == id) {
+        Some((key, stream)) => (key.clone(), stream),
+        None => return Err(respond::<()>(StatusCode::NOT_FOUND, "Stream not found", None)),
+    };
+
+    if !stream.paused {
+        return Ok(stream.clone());
+    }
+
+    stream.paused = false;
+    let generation = stream.generation;
+    let pipeline = stream.pipeline.clone();
+    drop(streams_lock);
+
+    let pipeline = match pipeline {
+        Some(pipeline) => pipeline,
+        // paused while it was between pipelines, start it over
+        None => return reload_stream(state, id).await,
+    };
+
+    if pipeline.set_state(gst::State::Playing).is_err() {
+        return Err(respond::<()>(StatusCode::INTERNAL_SERVER_ERROR, "Failed to resume pipeline", None));
+    }
+
+    update_stream(state, &key, generation, |s| {
+        s.state = StreamState::Running;
+        s.status = true;
+        s.message = String::from("Resumed");
+    }).await;
+
+    find_stream(state, id).await.ok_or_else(|| respond::<()>(StatusCode::NOT_FOUND, "Stream not found", None))
+}
+
+// applies what can change on the running pipeline in place and rebuilds for the rest, returning whether a rebuild was needed
+pub async fn reconfigure_stream(state: &AppState, id: &str, patch: StreamPatch) -> Result<(RtspStream, bool), Response> {
+    validate_metadata(patch.name.as_ref(), patch.group.as_ref(), patch.labels.as_ref())
+        .map_err(|e| respond::<()>(StatusCode::UNPROCESSABLE_ENTITY, e, None))?;
+
+    if let Some(webrtc_options) = &patch.webrtc_options {
+        webrtc_options.validate_with(&state.config.ice).map_err(|e| respond::<()>(StatusCode::UNPROCESSABLE_ENTITY, e, None))?;
+    }
+
+    let mut streams_lock = state.streams.lock().await;
+
+    let key = match streams_lock.iter().find(|(_, s)| s.id == id) {
+        Some((key, _)) => key.clone(),
+        None => return Err(respond::<()>(StatusCode::NOT_FOUND, "Stream not found", None)),
+    };
+
+    let (owner, stream_type, encode_options, video_bitrate) = {
+        let stream = &streams_lock[key.as_str()];
+        let video_bitrate = match patch.video_bitrate {
+            Some(0) => None,
+            Some(bitrate) => Some(bitrate),
+            None => stream.video_bitrate,
         };
 
-        match create_pipeline(payload.stream_type.as_str()) {
-            Ok(pipeline) => {
-                pipeline.set_state(gst::State::Playing).unwrap();
-
-                let mut streams_lock = streams.lock().await;
-                streams_lock.insert(
-                    formatted_rtsp.clone(),
-                    RtspStream {
-                        status: true,
-                        message: String::from("Started"),
-                        pipeline: Some(pipeline.clone()),
-                    },
-                );
-                drop(streams_lock);
+        (stream.owner.clone(), stream.stream_type.clone(), patch.encode_options.clone().unwrap_or_else(|| stream.encode_options.clone()), video_bitrate)
+    };
 
-                if let Some(bus) = pipeline.bus() {
-                    bus.timed_pop_filtered(
-                        gst::ClockTime::NONE,
-                        &[gst::MessageType::Eos, gst::MessageType::Error],
-                    );
-                    let _ = pipeline.set_state(gst::State::Null);
-                    let mut streams_lock = streams.lock().await;
-
-                    if streams_lock.contains_key(formatted_rtsp.as_str()) {
-                        streams_lock.insert(formatted_rtsp.clone(),RtspStream{
-                            status: false,
-                            message: String::from("Pipeline Ended"),
-                            pipeline: None,
-                        });
-                    };
-
-                    drop(streams_lock);
-                } else {
-                    pipeline.set_state(gst::State::Null).unwrap();
-                    let mut streams_lock = streams.lock().await;
-                    streams_lock.insert(
-                        formatted_rtsp.clone(),
-                        RtspStream {
-                            status: false,
-                            message: String::from("Bus not initialized"),
-                            pipeline: None,
-                        },
-                    );
-                    drop(streams_lock);
-                }
-            }
-            Err(e) => {
-                let mut streams_lock = streams.lock().await;
-                streams_lock.insert(
-                    formatted_rtsp.clone(),
-                    RtspStream {
-                        status: false,
-                        message: e,
-                        pipeline: None,
-                    },
-                );
-                drop(streams_lock);
+    validate_video_bitrate(&stream_type, &encode_options, video_bitrate).map_err(|e| respond::<()>(StatusCode::UNPROCESSABLE_ENTITY, e, None))?;
+    check_capacity(&state.config.limits, &streams_lock, &key, &owner, transcode_cost(&stream_type, &encode_options)).map_err(IntoResponse::into_response)?;
+
+    let stream = match streams_lock.get_mut(key.as_str()) {
+        Some(stream) => stream,
+        None => return Err(respond::<()>(StatusCode::NOT_FOUND, "Stream not found", None)),
+    };
+
+    // a new ladder or encoder needs a new pipeline, a new bitrate doesn't
+    let mut structural = stream.encode_options != encode_options;
+    let bitrate_changed = stream.video_bitrate != video_bitrate;
+    stream.encode_options = encode_options;
+    stream.video_bitrate = video_bitrate;
+
+    // webrtcsink reads these when it sets up, so they need a new pipeline
+    if let Some(webrtc_options) = patch.webrtc_options {
+        structural |= stream.stream_type != "HLS" && stream.webrtc_options != webrtc_options;
+        stream.webrtc_options = webrtc_options;
+    }
+
+    if bitrate_changed && !structural {
+        if let Some(pipeline) = &stream.pipeline {
+            apply_video_bitrate(pipeline, &stream.id, &stream.stream_type, &stream.encode_options, stream.video_bitrate);
+        }
+    }
+
+    if let Some(hls_options) = patch.hls_options {
+        if let Ok(mut playlists) = stream.playlists.lock() {
+            playlists.set_options(hls_options.max_files, hls_options.duration);
+        }
+
+        if let Some(pipeline) = &stream.pipeline {
+            for sink in find_elements(pipeline.upcast_ref(), "hlssink2") {
+                sink.set_property("target-duration", hls_options.duration);
             }
         }
 
-    });
+        stream.hls_options = Some(hls_options);
+    }
+
+    // metadata only matters to the API, the pipeline never sees it
+    if patch.name.is_some() {
+        stream.name = patch.name;
+    }
+
+    if patch.group.is_some() {
+        stream.group = patch.group;
+    }
+
+    if let Some(labels) = patch.labels {
+        stream.labels = labels;
+    }
+
+    // applies to new sessions, viewers already watching stay connected
+    if let Some(max_viewers) = patch.max_viewers {
+        stream.max_viewers = if max_viewers == 0 { None } else { Some(max_viewers) };
+        state.signalling.set_max_viewers(&stream.id, stream.max_viewers.map(|max| max as usize));
+    }
+
+    let stream = stream.clone();
+    drop(streams_lock);
+
+    if structural {
+        reload_stream(state, id).await.map(|stream| (stream, true))
+    } else {
+        Ok((stream, false))
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/addStream",
+    tag = "legacy",
+    request_body = StreamData,
+    responses(
+        (status = 200, description = "Stream starting", body = ErrorResponse),
+        (status = 422, description = "The source URL was rejected", body = ErrorResponse),
+        (status = 429, description = "Rate limit or per-key quota exceeded", body = ErrorResponse),
+        (status = 503, description = "Server stream or transcode capacity reached", body = ErrorResponse)
+    )
+)]
+pub async fn add_stream(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    Json(payload): Json<StreamData>,
+) -> Result<Json<ResponseData>, Response> {
+    start_stream(&state, &principal.name, payload, true).await?;
 
     let response = ResponseData {
         status: true,
@@ -103,26 +817,28 @@ pub async fn add_stream(State(streams): State<Arc<Mutex<HashMap<String, RtspStre
         data: None
     };
 
-    Json(response)
+    Ok(Json(response))
 }
 
+#[utoipa::path(
+    delete,
+    path = "/deleteStream",
+    tag = "legacy",
+    params(
+        ("rtsp" = String, Query, description = "Source URL"),
+        ("stream_type" = String, Query, description = "HLS or WEBRTC")
+    ),
+    responses(
+        (status = 200, description = "Stream deleted, or not found", body = ErrorResponse)
+    )
+)]
 pub async fn delete_stream(
     Query(params): Query<StreamData>,
-    State(streams): State<Arc<Mutex<HashMap<String, RtspStream>>>>,
+    State(state): State<AppState>,
 ) -> Json<ResponseData> {
-    let mut streams_lock = streams.lock().await;
-
-    let formatted_rtsp = format!("{}-{}",params.rtsp.clone(),params.stream_type.clone());
-
-    if let Some(stream) = streams_lock.get(formatted_rtsp.as_str()) {
-        if let Some(pipeline) = &stream.pipeline {
-            pipeline.send_event(gst::event::Eos::new());
-        }
-
-        streams_lock.remove(formatted_rtsp.as_str());
-
-        drop(streams_lock);
+    let id = stream_id(&params.rtsp, &params.stream_type);
 
+    if stop_stream(&state, &id).await.is_some() {
         Json(ResponseData {
             status: true,
             message: format!("Stream '{}' deleted successfully", &params.rtsp),
@@ -137,23 +853,277 @@ pub async fn delete_stream(
         })
     }
 }
-pub async fn get_streams(
-    State(streams): State<Arc<Mutex<HashMap<String, RtspStream>>>>,
-) -> Json<ResponseData<HashMap<String, RtspResponse>>> {
-    let data = streams.lock().await;
 
-    let mut new_data : HashMap<String,RtspResponse> = HashMap::new();
+// a JSON object that keeps the requested sort order
+struct OrderedStreams(Vec<(String, RtspResponse)>);
 
-    for (key, value) in data.iter() {
-        new_data.insert(key.clone(),RtspResponse{
-            status: value.status,
-            message: value.message.clone()
-        });
+impl Serialize for OrderedStreams {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.0.iter().map(|(key, value)| (key, value)))
     }
+}
+
+#[utoipa::path(
+    get,
+    path = "/getStreams",
+    tag = "legacy",
+    params(StreamQuery),
+    responses(
+        (status = 200, description = "Streams keyed by source and type, with the match count in `X-Total-Count`", body = ResponseData<HashMap<String, RtspResponse>>),
+        (status = 400, description = "Invalid selector, sort or order", body = ErrorResponse)
+    )
+)]
+pub async fn get_streams(
+    Query(query): Query<StreamQuery>,
+    State(state): State<AppState>,
+) -> Response {
+    let (streams, total) = match select_streams(&state, &query).await {
+        Ok(selected) => selected,
+        Err(response) => return response,
+    };
+
+    let new_data: Vec<(String, RtspResponse)> = streams.into_iter().map(|value| (format!("{}-{}", value.rtsp, value.stream_type), RtspResponse{
+        queue_position: state.scheduler.position(&value.id),
+        id: value.id,
+        name: value.name,
+        group: value.group,
+        labels: value.labels,
+        state: value.state,
+        status: value.status,
+        message: value.message
+    })).collect();
 
-    Json(ResponseData{
+    with_total(Json(ResponseData{
         status: true,
         message: String::from("Fetch Success"),
-        data: Some(new_data.clone())
-    })
-}
\ No newline at end of file
+        data: Some(OrderedStreams(new_data))
+    }).into_response(), total)
+}
+
+#[utoipa::path(
+    get,
+    path = "/streams/{id}",
+    tag = "legacy",
+    params(("id" = String, Path, description = "Stream ID")),
+    responses(
+        (status = 200, description = "Stream details, `status` is false if not found", body = ResponseData<StreamInfo>)
+    )
+)]
+pub async fn get_stream(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+) -> Json<ResponseData<StreamInfo>> {
+    match find_stream(&state, &id).await {
+        Some(stream) => Json(ResponseData {
+            status: true,
+            message: String::from("Fetch Success"),
+            data: Some(stream_info(&state, &stream))
+        }),
+        None => Json(ResponseData {
+            status: false,
+            message: String::from("Stream not found"),
+            data: None
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use gstreamer::prelude::GstBinExt;
+    use tokio::net::TcpListener;
+    use tokio::sync::{broadcast, Notify};
+    use crate::accounting::HlsAccounting;
+    use crate::config::Config;
+    use crate::demand::touch_stream;
+    use crate::limits::RateLimiter;
+    use crate::metrics::Metrics;
+    use crate::paths::stream_dir;
+    use crate::scheduler::Scheduler;
+    use crate::signalling::spawn_server;
+    use crate::webhooks::Webhooks;
+    use super::*;
+
+    fn app_state(config: Config) -> AppState {
+        let (_server, signalling, _demands) = spawn_server(None);
+        let (events, _) = broadcast::channel(64);
+
+        AppState {
+            scheduler: Arc::new(Scheduler::new(&config.startup)),
+            config: Arc::new(config),
+            streams: Arc::new(Mutex::new(HashMap::new())),
+            metrics: Arc::new(Metrics::new()),
+            events,
+            webhooks: Arc::new(Webhooks::new()),
+            signalling,
+            limiter: Arc::new(RateLimiter::new()),
+            hls: Arc::new(HlsAccounting::new()),
+        }
+    }
+
+    // accepts RTSP connections and never answers, so a pipeline reaches Playing without any media arriving
+    async fn silent_source() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let rtsp = format!("rtsp://{}/stream", listener.local_addr().unwrap());
+
+        tokio::spawn(async move {
+            let mut connections = vec![];
+
+            while let Ok((socket, _)) = listener.accept().await {
+                connections.push(socket);
+            }
+        });
+
+        rtsp
+    }
+
+    // registers an HLS stream the way start_stream does, minus the source validation that rejects loopback
+    async fn add_stream(state: &AppState, rtsp: &str, encode_options: EncodeOptions, on_demand: bool) -> (String, u64) {
+        let generation = GENERATION.fetch_add(1, Ordering::SeqCst);
+        let key = format!("{}-HLS", rtsp);
+
+        let stream = RtspStream {
+            id: stream_id(rtsp, "HLS"),
+            rtsp: rtsp.to_string(),
+            stream_type: String::from("HLS"),
+            encode_options,
+            hls_options: None,
+            video_bitrate: None,
+            state: StreamState::Starting,
+            status: false,
+            message: String::from("Starting"),
+            pipeline: None,
+            started_at: None,
+            reconnects: 0,
+            generation,
+            stats: Arc::new(std::sync::Mutex::new(StreamStats::default())),
+            owner: String::from("test"),
+            playlists: Arc::new(std::sync::Mutex::new(HlsPlaylists::new(5, 2))),
+            paused: false,
+            priority: 0,
+            name: None,
+            group: None,
+            labels: BTreeMap::new(),
+            on_demand,
+            last_demand: None,
+            wake: Arc::new(Notify::new()),
+            viewers: Arc::new(std::sync::Mutex::new(ViewerRegistry::default())),
+            max_viewers: None,
+            webrtc_options: WebRtcOptions::default(),
+        };
+
+        state.streams.lock().await.insert(key.clone(), stream);
+
+        (key, generation)
+    }
+
+    async fn wait_for_pipeline(state: &AppState, key: &str) -> gst::Pipeline {
+        let deadline = Instant::now() + Duration::from_secs(10);
+
+        loop {
+            if let Some(pipeline) = state.streams.lock().await.get(key).and_then(|s| s.pipeline.clone()) {
+                return pipeline;
+            }
+
+            assert!(Instant::now() < deadline, "no pipeline was started");
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    }
+
+    async fn remove_stream(state: &AppState, id: &str) {
+        stop_stream(state, id).await;
+        let _ = std::fs::remove_dir_all(stream_dir(id).unwrap());
+    }
+
+    #[tokio::test]
+    async fn holds_the_start_slot_until_media_flows() {
+        gst::init().unwrap();
+
+        let mut config = Config::default();
+        config.startup.parallelism = 1;
+        let state = app_state(config);
+
+        let rtsp = silent_source().await;
+        let (key, generation) = add_stream(&state, &rtsp, EncodeOptions::NONE, false).await;
+        let id = stream_id(&rtsp, "HLS");
+        let mut events = state.events.subscribe();
+
+        tokio::spawn(supervise_stream(state.clone(), key.clone(), generation));
+        let pipeline = wait_for_pipeline(&state, &key).await;
+
+        // the pipeline is playing, but the source hasn't sent anything yet
+        assert_eq!(find_stream(&state, &id).await.unwrap().state, StreamState::Starting);
+        assert!(state.scheduler.try_acquire("rtsp://camera.test/stream").is_none());
+
+        // what attach_stats posts once the first buffer leaves the parser
+        pipeline.bus().unwrap().post(gst::message::Application::new(gst::Structure::new_empty(MEDIA_MESSAGE))).unwrap();
+
+        let running = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                if let Ok(StreamEvent { kind: StreamEventKind::StateChanged { state: StreamState::Running, .. }, .. }) = events.recv().await {
+                    break;
+                }
+            }
+        }).await;
+
+        assert!(running.is_ok(), "the stream never reported running");
+        assert!(state.scheduler.try_acquire("rtsp://camera.test/stream").is_some());
+
+        remove_stream(&state, &id).await;
+    }
+
+    #[tokio::test]
+    async fn wakes_for_a_demand_that_arrives_while_it_checks() {
+        gst::init().unwrap();
+
+        let state = app_state(Config::default());
+        let rtsp = silent_source().await;
+        let (key, generation) = add_stream(&state, &rtsp, EncodeOptions::NONE, true).await;
+        let id = stream_id(&rtsp, "HLS");
+
+        // the supervisor queues for the streams lock behind this guard
+        let guard = state.streams.lock().await;
+        tokio::spawn(supervise_stream(state.clone(), key.clone(), generation));
+        tokio::task::yield_now().await;
+
+        // the lock is fair, so the supervisor reads the stream, then this demand lands before it decides to go idle
+        drop(guard);
+        assert!(touch_stream(&state, &id).await);
+
+        wait_for_pipeline(&state, &key).await;
+
+        remove_stream(&state, &id).await;
+    }
+
+    #[tokio::test]
+    async fn changes_the_bitrate_on_the_running_encoder() {
+        gst::init().unwrap();
+
+        let state = app_state(Config::default());
+        let rtsp = silent_source().await;
+        let (key, generation) = add_stream(&state, &rtsp, EncodeOptions::SINGLE, false).await;
+        let id = stream_id(&rtsp, "HLS");
+
+        tokio::spawn(supervise_stream(state.clone(), key.clone(), generation));
+        let pipeline = wait_for_pipeline(&state, &key).await;
+
+        let patch = StreamPatch {
+            encode_options: None,
+            hls_options: None,
+            video_bitrate: Some(1500),
+            name: None,
+            group: None,
+            labels: None,
+            max_viewers: None,
+            webrtc_options: None
+        };
+        let (stream, rebuilt) = reconfigure_stream(&state, &id, patch).await.ok().expect("patch rejected");
+
+        assert!(!rebuilt);
+        assert_eq!(stream.video_bitrate, Some(1500));
+        assert_eq!(stream.pipeline.as_ref(), Some(&pipeline));
+        assert_eq!(pipeline.by_name("encoder-1080p").unwrap().property::<u32>("bitrate"), 1500);
+
+        remove_stream(&state, &id).await;
+    }
+}
@@ -3,24 +3,38 @@
 
     use std::fs;
     use std::path::Path;
+    use std::sync::atomic::{AtomicBool, Ordering};
     use std::sync::{Arc, Mutex};
     use gstreamer as gst;
     use gstreamer::{Element, Pipeline};
     use gstreamer::prelude::*;
-    use crate::structures::{EncodeOptions, HLSOptions};
+    use crate::ice::configure_sink;
+    use crate::paths::{migrate_legacy_dir, stream_dir};
+    use crate::structures::{CongestionControl, EncodeOptions, HLSOptions, StreamStats, WebRtcOptions};
 
     // default values
     const MAX_FILES: u32 = 17280;
     const DURATION: u32 = 2;
+    // posted on the bus once the source's first buffer has been parsed
+    pub const MEDIA_MESSAGE: &str = "rtsp-media";
+    // x264enc's own default, in kbit/s
+    const DEFAULT_ENCODER_BITRATE: u32 = 2048;
 
 
+    pub fn default_hls_options() -> HLSOptions {
+        HLSOptions {
+            max_files: MAX_FILES,
+            duration: DURATION
+        }
+    }
+
     pub struct Quality{
         width: i32,
         height: i32,
         bitrate: u32
     }
 
-    fn create_dynamic_pipeline(parser: Element,encode_options: EncodeOptions, sink: Element, pipeline: Pipeline) -> Result<(),String>{
+    fn create_dynamic_pipeline(parser: Element,encode_options: EncodeOptions, video_bitrate: Option<u32>, sink: Element, pipeline: Pipeline) -> Result<(),String>{
         match encode_options {
             EncodeOptions::NONE => {
                 if let Some(sink_pad) = sink.request_pad_simple("video_%u") {
@@ -53,12 +67,13 @@
                     Ok(element) => element,
                     Err(_) => return Err("Failed to create videoconvert".to_string()),
                 };
-                let enc = match gst::ElementFactory::make("x264enc").build() {
+                let enc = match gst::ElementFactory::make("x264enc").name("encoder").build() {
                     Ok(element) => element,
                     Err(_) => return Err("Failed to create x264enc".to_string()),
                 };
+                enc.set_property("bitrate", video_bitrate.unwrap_or(DEFAULT_ENCODER_BITRATE));
 
-                let queue = match gst::ElementFactory::make("queue").build() {
+                let queue = match gst::ElementFactory::make("queue").name("encoder-queue").build() {
                     Ok(element) => element,
                     Err(_) => return Err("Failed to create queue".to_string()),
                 };
@@ -171,7 +186,178 @@
 
     }
 
-    pub fn create_webrtc_pipeline(rtsp: &str, encode_options: EncodeOptions) -> Result<Pipeline, String> {
+    fn attach_stats(parser: &Element, stats: Arc<Mutex<StreamStats>>) {
+        let flowing = Arc::new(AtomicBool::new(false));
+
+        parser.connect("pad-added", false, move |values| {
+            let pad = match values[1].get::<gst::Pad>() {
+                Ok(pad) => pad,
+                Err(_) => return None,
+            };
+
+            // bitrate, fps and input caps describe the video, an audio track would double the frame count
+            let caps = pad.current_caps().unwrap_or_else(|| pad.query_caps(None));
+            let video = caps.structure(0).map(|s| s.name().starts_with("video/")).unwrap_or(false);
+
+            if video {
+                if let Ok(mut s) = stats.lock() {
+                    s.input_caps = Some(caps.to_string());
+                }
+            }
+
+            let probe_stats = stats.clone();
+            let probe_flowing = flowing.clone();
+
+            pad.add_probe(gst::PadProbeType::BUFFER | gst::PadProbeType::EVENT_DOWNSTREAM, move |pad, info| {
+                match info.data {
+                    Some(gst::PadProbeData::Buffer(ref buffer)) => {
+                        if video {
+                            if let Ok(mut s) = probe_stats.lock() {
+                                s.record_buffer(buffer.size());
+                            }
+                        }
+
+                        // the pipeline reaches Playing before rtspsrc has even sent DESCRIBE, so this is what tells the supervisor the source is up
+                        if !probe_flowing.swap(true, Ordering::Relaxed) {
+                            if let Some(parser) = pad.parent_element() {
+                                let _ = parser.post_message(gst::message::Application::builder(gst::Structure::new_empty(MEDIA_MESSAGE)).src(&parser).build());
+                            }
+                        }
+                    }
+                    Some(gst::PadProbeData::Event(ref event)) if video => {
+                        if let gst::EventView::Caps(caps) = event.view() {
+                            if let Ok(mut s) = probe_stats.lock() {
+                                s.input_caps = Some(caps.caps().to_string());
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+
+                gst::PadProbeReturn::Ok
+            });
+
+            None
+        });
+    }
+
+    pub fn find_elements(bin: &gst::Bin, factory_name: &str) -> Vec<Element> {
+        let mut found = vec![];
+
+        for element in bin.children() {
+            if element.factory().map(|f| f.name().as_str() == factory_name).unwrap_or(false) {
+                found.push(element.clone());
+            }
+
+            if let Some(child) = element.downcast_ref::<gst::Bin>() {
+                found.extend(find_elements(child, factory_name));
+            }
+        }
+
+        found
+    }
+
+    pub fn pipeline_output_caps(pipeline: &Pipeline) -> Vec<String> {
+        let mut caps = vec![];
+
+        for factory_name in ["hlssink2", "webrtcsink"] {
+            for sink in find_elements(pipeline.upcast_ref(), factory_name) {
+                for pad in sink.sink_pads() {
+                    if let Some(current) = pad.current_caps() {
+                        caps.push(current.to_string());
+                    }
+                }
+            }
+        }
+
+        caps
+    }
+
+    // RTP packets the jitterbuffers gave up on, lost in transit or arriving too late to be played
+    pub fn pipeline_lost_packets(pipeline: &Pipeline) -> u64 {
+        find_elements(pipeline.upcast_ref(), "rtpjitterbuffer")
+            .iter()
+            .map(|jitterbuffer| {
+                let stats = jitterbuffer.property::<gst::Structure>("stats");
+                stats.get::<u64>("num-lost").unwrap_or(0) + stats.get::<u64>("num-late").unwrap_or(0)
+            })
+            .sum()
+    }
+
+    // only the queues right after the encoders (`encoder-queue`, or `encoder-queue-<height>p` per MULTI rendition),
+    // the scaler and decoder queues say nothing about encoder load
+    pub fn pipeline_queue_levels(pipeline: &Pipeline) -> Vec<(String, u32, u64)> {
+        find_elements(pipeline.upcast_ref(), "queue")
+            .iter()
+            .filter(|queue| queue.name().starts_with("encoder-queue"))
+            .map(|queue| (
+                queue.name().to_string(),
+                queue.property::<u32>("current-level-buffers"),
+                queue.property::<u64>("current-level-time"),
+            ))
+            .collect()
+    }
+
+    fn stats_type(structure: &gst::StructureRef) -> String {
+        let from_field = structure.value("type").ok()
+            .and_then(|value| value.serialize().ok())
+            .map(|value| value.to_string());
+
+        from_field.unwrap_or_else(|| structure.name().to_string())
+    }
+
+    pub fn walk_stats(structure: &gst::StructureRef, visit: &mut dyn FnMut(&str, &gst::StructureRef)) {
+        visit(stats_type(structure).as_str(), structure);
+
+        for (_, value) in structure.iter() {
+            if let Ok(child) = value.get::<gst::Structure>() {
+                walk_stats(&child, visit);
+            }
+        }
+    }
+
+    fn apply_webrtc_options(sink: &Element, options: &WebRtcOptions, encode_options: &EncodeOptions) {
+        // adaptive streams follow the network with GCC unless told otherwise
+        let congestion_control = options.congestion_control.or(match encode_options {
+            EncodeOptions::MULTI => Some(CongestionControl::Gcc),
+            _ => None,
+        });
+
+        if let Some(congestion_control) = congestion_control {
+            sink.set_property_from_str("congestion-control", congestion_control.as_str());
+        }
+
+        if let Some(min_bitrate) = options.min_bitrate {
+            sink.set_property("min-bitrate", min_bitrate);
+        }
+
+        if let Some(max_bitrate) = options.max_bitrate {
+            sink.set_property("max-bitrate", max_bitrate);
+        }
+
+        if let Some(start_bitrate) = options.start_bitrate {
+            sink.set_property("start-bitrate", start_bitrate);
+        }
+
+        if let Some(codecs) = &options.video_codecs {
+            let caps: gst::Caps = codecs.iter().map(|codec| gst::Structure::new_empty(codec.caps_name())).collect();
+            sink.set_property("video-caps", caps);
+        }
+
+        if let Some(fec) = options.fec {
+            sink.set_property("do-fec", fec);
+        }
+
+        if let Some(retransmission) = options.retransmission {
+            sink.set_property("do-retransmission", retransmission);
+        }
+
+        if let Some(clock_signalling) = options.clock_signalling {
+            sink.set_property("do-clock-signalling", clock_signalling);
+        }
+    }
+
+    pub fn create_webrtc_pipeline(rtsp: &str, id: &str, encode_options: EncodeOptions, video_bitrate: Option<u32>, webrtc_options: &WebRtcOptions, stats: Arc<Mutex<StreamStats>>, signaller_uri: String) -> Result<Pipeline, String> {
         let pipeline = Pipeline::new();
 
         let src = match gst::ElementFactory::make("rtspsrc").build() {
@@ -192,8 +378,16 @@
 
         let mut meta = gst::Structure::new_empty("meta");
         meta.set("rtsp",rtsp);
+        meta.set("stream-id", id);
 
         sink.set_property("meta", meta);
+        apply_webrtc_options(&sink, webrtc_options, &encode_options);
+        if let Some(ice) = &webrtc_options.ice {
+            configure_sink(&sink, ice, id);
+        }
+
+        let signaller = sink.property::<gst::glib::Object>("signaller");
+        signaller.set_property("uri", signaller_uri);
 
         if let Err(e) = pipeline.add_many(&[&src,&parse, &sink]) {
             return Err(format!("Failed to add elements to pipeline: {:?}", e));
@@ -217,8 +411,9 @@
             None
         });
 
+        attach_stats(&parse, stats);
 
-        if let Err(E) = create_dynamic_pipeline(parse, encode_options, sink,pipeline.clone()){
+        if let Err(E) = create_dynamic_pipeline(parse, encode_options, video_bitrate, sink,pipeline.clone()){
             return Err(E)
         }
 
@@ -227,8 +422,8 @@
 
 
 
-    fn create_quality_elements(quality: Quality, pipeline: Pipeline, tee: &Element, rtsp: String, hls_options: HLSOptions){
-        let queue = gst::ElementFactory::make("queue").build().unwrap();
+    fn create_quality_elements(quality: Quality, pipeline: Pipeline, tee: &Element, output_dir: &Path, hls_options: HLSOptions){
+        let queue = gst::ElementFactory::make("queue").name(format!("scale-queue-{}p", quality.height)).build().unwrap();
         let scale = gst::ElementFactory::make("videoscale").build().unwrap();
         let caps = gst::Caps::builder("video/x-raw")
             .field("width", quality.width)
@@ -238,21 +433,20 @@
         filter.set_property("caps", &caps);
 
         let convert = gst::ElementFactory::make("videoconvert").build().unwrap();
-        let encode = gst::ElementFactory::make("x264enc").build().unwrap();
+        let encode = gst::ElementFactory::make("x264enc").name(format!("encoder-{}p", quality.height)).build().unwrap();
         encode.set_property("bitrate",quality.bitrate);
 
-        let queue1 = gst::ElementFactory::make("queue").build().unwrap();
+        let queue1 = gst::ElementFactory::make("queue").name(format!("encoder-queue-{}p", quality.height)).build().unwrap();
 
         let parse = gst::ElementFactory::make("h264parse").build().unwrap();
 
-        let rtsp_dir = format!("{}/{}", "./hls", rtsp.replace(&['/', ':', '?', '&'][..], "_"));
-
         let hlssink = gst::ElementFactory::make("hlssink2").build().unwrap();
-        hlssink.set_property("location", format!("{}/{}p/segment%05d.ts",rtsp_dir,quality.height));
-        hlssink.set_property("playlist-location", format!("{}/{}p/playlist.m3u8",rtsp_dir,quality.height));
+        hlssink.set_property("location", format!("{}/{}p/segment%05d.ts",output_dir.display(),quality.height));
+        // the served playlist is written by HlsPlaylists, which also removes expired segments
+        hlssink.set_property("playlist-location", format!("{}/{}p/.sink.m3u8",output_dir.display(),quality.height));
         hlssink.set_property("target-duration", hls_options.duration);
-        hlssink.set_property("max-files", hls_options.max_files);
-        hlssink.set_property("playlist-length", hls_options.max_files);
+        hlssink.set_property("max-files", 0u32);
+        hlssink.set_property("playlist-length", 1u32);
 
         pipeline.add_many(&[
             &queue,
@@ -279,13 +473,10 @@
     }
 
 
-    pub fn create_hls_pipeline(rtsp: &str, qualities: Vec<Quality>, encode_options: EncodeOptions, hls_options: Option<HLSOptions> ) -> Result<Pipeline, String> {
+    pub fn create_hls_pipeline(rtsp: &str, output_dir: &Path, qualities: Vec<Quality>, encode_options: EncodeOptions, hls_options: Option<HLSOptions>, stats: Arc<Mutex<StreamStats>>, start_index: u64) -> Result<Pipeline, String> {
         let pipeline = Pipeline::new();
 
-        let formatted_hls_options = hls_options.unwrap_or_else(|| HLSOptions{
-            max_files: MAX_FILES,
-            duration: DURATION
-        });
+        let formatted_hls_options = hls_options.unwrap_or_else(default_hls_options);
 
         let rtspsrc = match gst::ElementFactory::make("rtspsrc").build() {
             Ok(element) => element,
@@ -307,14 +498,13 @@
         let sink ;
 
         if let EncodeOptions::NONE = encode_options {
-            let rtsp_dir = format!("{}/{}", "./hls", rtsp.replace(&['/', ':', '?', '&'][..], "_"));
-
             let hlssink = gst::ElementFactory::make("hlssink2").build().unwrap();
-            hlssink.set_property("location", format!("{}/{}p/segment%05d.ts",rtsp_dir,1080));
-            hlssink.set_property("playlist-location", format!("{}/{}p/playlist.m3u8",rtsp_dir,1080));
+            hlssink.set_property("location", format!("{}/{}p/segment%05d.ts",output_dir.display(),1080));
+            // the served playlist is written by HlsPlaylists, which also removes expired segments
+            hlssink.set_property("playlist-location", format!("{}/{}p/.sink.m3u8",output_dir.display(),1080));
             hlssink.set_property("target-duration", formatted_hls_options.duration);
-            hlssink.set_property("max-files", formatted_hls_options.max_files);
-            hlssink.set_property("playlist-length", formatted_hls_options.max_files);
+            hlssink.set_property("max-files", 0u32);
+            hlssink.set_property("playlist-length", 1u32);
 
             if let Err(e) = pipeline.add_many(&[&hlssink]){
                 return Err(format!("Failed to add elements to pipeline: {:?}", e));
@@ -341,7 +531,7 @@
 
 
             for quality in qualities {
-                create_quality_elements(quality, pipeline.clone(), &tee, rtsp.to_string(), formatted_hls_options.clone());
+                create_quality_elements(quality, pipeline.clone(), &tee, output_dir, formatted_hls_options.clone());
             }
 
             decode.connect("pad-added", false, move |values| {
@@ -383,6 +573,8 @@
             None
         });
 
+        attach_stats(&parse, stats);
+
         let d = sink.clone();
         let pipe = pipeline.clone();
 
@@ -440,11 +632,16 @@
 
 
 
+        // continue the previous pipeline's segment numbering
+        for splitmux in find_elements(pipeline.upcast_ref(), "splitmuxsink") {
+            splitmux.set_property("start-index", start_index.min(i32::MAX as u64) as i32);
+        }
+
         Ok(pipeline)
     }
 
 
-    pub fn create_and_manage_playlists(rtsp: String, encode_options: EncodeOptions)-> Vec<Quality>{
+    fn quality_ladder(encode_options: &EncodeOptions, video_bitrate: Option<u32>) -> Vec<Quality> {
         let mut qualities = vec![];
 
         if let EncodeOptions::MULTI = encode_options {
@@ -458,28 +655,26 @@
             qualities.push(Quality { width: 1920, height: 1080, bitrate: 4000 });
         }
 
-        let hls_dir = "./hls";
+        // a set bitrate replaces the top rendition's, the lower ones keep their proportion to it
+        if let Some(top) = video_bitrate {
+            let reference = u64::from(qualities[0].bitrate);
 
-        let rtsp_dir = format!("{}/{}", hls_dir, rtsp.replace(&['/', ':', '?', '&'][..], "_"));
-        if Path::new(&rtsp_dir).exists() {
-            fs::remove_dir_all(&rtsp_dir).unwrap();
-        }
-
-        for quality in &qualities {
-            let quality_dir = format!("{}/{}p", rtsp_dir, quality.height);
-            if Path::new(&quality_dir).exists() {
-                fs::remove_dir_all(&quality_dir).unwrap();
+            for quality in &mut qualities {
+                quality.bitrate = (u64::from(quality.bitrate) * u64::from(top) / reference).max(1) as u32;
             }
-            fs::create_dir_all(&quality_dir).unwrap();
         }
 
-        let master_playlist_path = format!("{}/master.m3u", rtsp_dir);
+        qualities
+    }
+
+    fn write_master_playlist(rtsp_dir: &Path, qualities: &[Quality]) {
+        let master_playlist_path = rtsp_dir.join("master.m3u");
         let mut master_playlist = String::new();
 
         master_playlist.push_str("#EXTM3U\n");
         master_playlist.push_str("#EXT-X-VERSION:3\n\n");
 
-        for quality in &qualities {
+        for quality in qualities {
             let playlist_entry = format!(
                 "#EXT-X-STREAM-INF:BANDWIDTH={},RESOLUTION={}x{}\n{}/playlist.m3u8\n",
                 quality.bitrate * 1000,
@@ -491,6 +686,59 @@
         }
 
         let _ = fs::write(master_playlist_path, master_playlist);
+    }
 
-        qualities
+    // x264enc takes a new bitrate while playing, so changing it doesn't need a new pipeline
+    pub fn apply_video_bitrate(pipeline: &Pipeline, id: &str, stream_type: &str, encode_options: &EncodeOptions, video_bitrate: Option<u32>) {
+        if stream_type != "HLS" {
+            if let Some(encoder) = pipeline.by_name("encoder") {
+                encoder.set_property("bitrate", video_bitrate.unwrap_or(DEFAULT_ENCODER_BITRATE));
+            }
+            return;
+        }
+
+        let qualities = quality_ladder(encode_options, video_bitrate);
+
+        for quality in &qualities {
+            if let Some(encoder) = pipeline.by_name(&format!("encoder-{}p", quality.height)) {
+                encoder.set_property("bitrate", quality.bitrate);
+            }
+        }
+
+        if let Ok(rtsp_dir) = stream_dir(id) {
+            write_master_playlist(&rtsp_dir, &qualities);
+        }
+    }
+
+    // `fresh` clears previous output, otherwise segments are kept so a rebuilt pipeline can continue the playlists
+    pub fn create_and_manage_playlists(rtsp: &str, id: &str, encode_options: EncodeOptions, video_bitrate: Option<u32>, fresh: bool)-> Result<(std::path::PathBuf, Vec<Quality>), String>{
+        let qualities = quality_ladder(&encode_options, video_bitrate);
+
+        let rtsp_dir = stream_dir(id)?;
+
+        // a migrated directory is continued like a rebuilt pipeline's
+        let migrated = migrate_legacy_dir(rtsp, &rtsp_dir);
+        if fresh && !migrated && rtsp_dir.exists() {
+            fs::remove_dir_all(&rtsp_dir).map_err(|e| format!("Failed to clear {}: {}", rtsp_dir.display(), e))?;
+        }
+
+        // variants dropped from the ladder
+        if let Ok(entries) = fs::read_dir(&rtsp_dir) {
+            for entry in entries.flatten() {
+                let name = entry.file_name().to_string_lossy().to_string();
+
+                if entry.path().is_dir() && !qualities.iter().any(|q| format!("{}p", q.height) == name) {
+                    let _ = fs::remove_dir_all(entry.path());
+                }
+            }
+        }
+
+        for quality in &qualities {
+            let quality_dir = rtsp_dir.join(format!("{}p", quality.height));
+            fs::create_dir_all(&quality_dir).map_err(|e| format!("Failed to create {}: {}", quality_dir.display(), e))?;
+        }
+
+        write_master_playlist(&rtsp_dir, &qualities);
+
+        Ok((rtsp_dir, qualities))
     }
@@ -1,7 +1,20 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use axum::extract::FromRef;
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use crate::accounting::HlsAccounting;
+use crate::config::Config;
+use crate::limits::RateLimiter;
+use crate::metrics::Metrics;
+use crate::playlist::HlsPlaylists;
+use crate::scheduler::Scheduler;
+use crate::signalling::SignallingGate;
+use crate::viewers::ViewerRegistry;
+use crate::webhooks::Webhooks;
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, ToSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum EncodeOptions{
     NONE,
@@ -9,36 +22,568 @@ pub enum EncodeOptions{
     MULTI
 }
 
-#[derive(Deserialize,Clone)]
+// x264enc's upper limit, in kbit/s
+const MAX_VIDEO_BITRATE: u32 = 2048000;
+
+// the bitrate is for encoders the server runs itself; webrtcsink encodes MULTI WebRTC streams and adapts its own bitrate
+pub fn validate_video_bitrate(stream_type: &str, encode_options: &EncodeOptions, video_bitrate: Option<u32>) -> Result<(), String> {
+    match (video_bitrate, encode_options) {
+        (None, _) => Ok(()),
+        (Some(0), _) => Err(String::from("video_bitrate must be above 0")),
+        (Some(_), EncodeOptions::NONE) => Err(String::from("video_bitrate needs encode_options single or multi")),
+        (Some(_), EncodeOptions::MULTI) if stream_type != "HLS" => Err(String::from("video_bitrate doesn't apply to MULTI WebRTC streams, use the webrtc_options bitrates")),
+        (Some(bitrate), _) if bitrate > MAX_VIDEO_BITRATE => Err(format!("video_bitrate must be at most {}", MAX_VIDEO_BITRATE)),
+        _ => Ok(()),
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, ToSchema)]
 pub struct HLSOptions {
    pub max_files: u32,
     pub duration: u32
 }
 
-#[derive(Deserialize)]
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum CongestionControl {
+    Gcc,
+    Homegrown,
+    Disabled
+}
+
+impl CongestionControl {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CongestionControl::Gcc => "gcc",
+            CongestionControl::Homegrown => "homegrown",
+            CongestionControl::Disabled => "disabled",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, ToSchema)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum VideoCodec {
+    H264,
+    VP8,
+    VP9,
+    H265
+}
+
+impl VideoCodec {
+    pub fn caps_name(&self) -> &'static str {
+        match self {
+            VideoCodec::H264 => "video/x-h264",
+            VideoCodec::VP8 => "video/x-vp8",
+            VideoCodec::VP9 => "video/x-vp9",
+            VideoCodec::H265 => "video/x-h265",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum IceTransportPolicy {
+    All,
+    Relay
+}
+
+impl IceTransportPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IceTransportPolicy::All => "all",
+            IceTransportPolicy::Relay => "relay",
+        }
+    }
+}
+
+fn default_turn_ttl() -> u64 { 86400 }
+
+// `url` is written the way browsers take it, e.g. `turn:turn.example.com:3478?transport=tcp` or `turns:...`
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct TurnServer {
+    pub url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub credential: Option<String>,
+    // shared secret for time-limited credentials (TURN REST API), used instead of username and credential
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+    // seconds generated credentials stay valid
+    #[serde(default = "default_turn_ttl")]
+    pub ttl: u64
+}
+
+// ICE settings; on a stream, each field that is set overrides the server-wide `ice` config
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, ToSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct IceOptions {
+    // `stun:host:port`; an empty string disables STUN, unset keeps webrtcsink's default public server
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stun_server: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub turn_servers: Option<Vec<TurnServer>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transport_policy: Option<IceTransportPolicy>,
+    // UDP port range for the server's candidates
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_port: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_port: Option<u16>
+}
+
+impl IceOptions {
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(stun) = self.stun_server.as_deref().filter(|s| !s.is_empty()) {
+            if !stun.starts_with("stun:") {
+                return Err(format!("Invalid STUN server '{}', expected stun:host:port", stun));
+            }
+        }
+
+        for server in self.turn_servers.iter().flatten() {
+            if !server.url.starts_with("turn:") && !server.url.starts_with("turns:") {
+                return Err(format!("Invalid TURN server '{}', expected turn:host:port or turns:host:port", server.url));
+            }
+
+            let static_credentials = server.username.is_some() && server.credential.is_some();
+            if static_credentials == server.secret.is_some() {
+                return Err(format!("TURN server '{}' needs either username and credential or a secret", server.url));
+            }
+
+            if server.secret.is_some() && server.ttl == 0 {
+                return Err(format!("TURN server '{}' needs a ttl above 0", server.url));
+            }
+        }
+
+        if self.min_port.unwrap_or(0) > self.max_port.unwrap_or(u16::MAX) || self.min_port == Some(0) {
+            return Err(String::from("min_port must be between 1 and max_port"));
+        }
+
+        Ok(())
+    }
+
+    // the stream's settings on top of the server-wide ones
+    pub fn merged(&self, global: &IceOptions) -> IceOptions {
+        IceOptions {
+            stun_server: self.stun_server.clone().or_else(|| global.stun_server.clone()),
+            turn_servers: self.turn_servers.clone().or_else(|| global.turn_servers.clone()),
+            transport_policy: self.transport_policy.or(global.transport_policy),
+            min_port: self.min_port.or(global.min_port),
+            max_port: self.max_port.or(global.max_port)
+        }
+    }
+
+    // hides TURN passwords and secrets from stream details
+    pub fn redacted(&self) -> IceOptions {
+        let mut options = self.clone();
+
+        for server in options.turn_servers.iter_mut().flatten() {
+            server.credential = server.credential.as_ref().map(|_| String::from("***"));
+            server.secret = server.secret.as_ref().map(|_| String::from("***"));
+        }
+
+        options
+    }
+}
+
+// webrtcsink settings, anything left out keeps the sink's default; bitrates are in bits per second
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct WebRtcOptions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub congestion_control: Option<CongestionControl>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_bitrate: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_bitrate: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_bitrate: Option<u32>,
+    // in order of preference
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub video_codecs: Option<Vec<VideoCodec>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fec: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retransmission: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub clock_signalling: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ice: Option<IceOptions>
+}
+
+impl WebRtcOptions {
+    pub fn redacted(&self) -> WebRtcOptions {
+        WebRtcOptions { ice: self.ice.as_ref().map(IceOptions::redacted), ..self.clone() }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        let min = self.min_bitrate.unwrap_or(0);
+        let max = self.max_bitrate.unwrap_or(u32::MAX);
+
+        if min > max {
+            return Err(String::from("min_bitrate must not exceed max_bitrate"));
+        }
+
+        if let Some(start) = self.start_bitrate {
+            if start < min || start > max {
+                return Err(String::from("start_bitrate must be between min_bitrate and max_bitrate"));
+            }
+        }
+
+        if self.video_codecs.as_ref().map(|codecs| codecs.is_empty()).unwrap_or(false) {
+            return Err(String::from("video_codecs must list at least one codec"));
+        }
+
+        if let Some(ice) = &self.ice {
+            ice.validate()?;
+        }
+
+        Ok(())
+    }
+
+    // a stream's ICE fields each override the server's, so a valid stream setting can still clash with the server's, e.g. a port range
+    pub fn validate_with(&self, global: &IceOptions) -> Result<(), String> {
+        self.validate()?;
+
+        match &self.ice {
+            Some(ice) => ice.merged(global).validate().map_err(|e| format!("{} (combined with the server's ice settings)", e)),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, ToSchema)]
 pub struct StreamData {
     pub rtsp: String,
     pub stream_type: String,
     pub encode_options: Option<EncodeOptions>,
-    pub hls_options: Option<HLSOptions>
+    pub hls_options: Option<HLSOptions>,
+    // kbit/s for the server's H.264 encoder; on MULTI HLS streams it sets the top rendition and the others scale with it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub video_bitrate: Option<u32>,
+    // higher values start first when the start queue is busy
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub priority: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub labels: Option<BTreeMap<String, String>>,
+    // only run the pipeline while someone is watching
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub on_demand: Option<bool>,
+    // concurrent WebRTC viewers allowed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_viewers: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub webrtc_options: Option<WebRtcOptions>
+}
+
+// fields that can change without changing the stream's identity
+#[derive(Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct StreamPatch {
+    pub encode_options: Option<EncodeOptions>,
+    pub hls_options: Option<HLSOptions>,
+    // applied to the running encoders; 0 returns to the default
+    pub video_bitrate: Option<u32>,
+    pub name: Option<String>,
+    pub group: Option<String>,
+    // replaces the stream's labels
+    pub labels: Option<BTreeMap<String, String>>,
+    // 0 removes the limit
+    pub max_viewers: Option<u32>,
+    // replaces the stream's WebRTC settings and rebuilds the pipeline
+    pub webrtc_options: Option<WebRtcOptions>
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct ResponseData<T = ()> {
     pub status: bool,
     pub message: String,
     pub data: Option<T>
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum StreamState {
+    Idle,
+    Queued,
+    Starting,
+    Running,
+    Reconnecting,
+    Paused,
+    Stopped,
+    Failed
+}
+
+impl StreamState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StreamState::Idle => "idle",
+            StreamState::Queued => "queued",
+            StreamState::Starting => "starting",
+            StreamState::Running => "running",
+            StreamState::Reconnecting => "reconnecting",
+            StreamState::Paused => "paused",
+            StreamState::Stopped => "stopped",
+            StreamState::Failed => "failed",
+        }
+    }
+}
+
+#[derive(Default, Debug)]
+pub struct StreamStats {
+    pub input_caps: Option<String>,
+    pub bytes: u64,
+    pub frames: u64,
+    pub bitrate: u64,
+    pub fps: f64,
+    last_buffer: Option<Instant>,
+    window_start: Option<Instant>,
+    window_bytes: u64,
+    window_frames: u64,
+    // latest QoS total per element path
+    dropped: HashMap<String, u64>
+}
+
+impl StreamStats {
+    pub fn record_buffer(&mut self, size: usize) {
+        let now = Instant::now();
+        let start = *self.window_start.get_or_insert(now);
+
+        self.bytes += size as u64;
+        self.frames += 1;
+        self.window_bytes += size as u64;
+        self.window_frames += 1;
+        self.last_buffer = Some(now);
+
+        let elapsed = now.duration_since(start).as_secs_f64();
+
+        if elapsed >= 1.0 {
+            self.bitrate = (self.window_bytes as f64 * 8.0 / elapsed) as u64;
+            self.fps = self.window_frames as f64 / elapsed;
+            self.window_start = Some(now);
+            self.window_bytes = 0;
+            self.window_frames = 0;
+        }
+    }
+
+    // QoS messages carry each element's running total rather than the frames dropped since the last one
+    pub fn record_dropped(&mut self, element: String, dropped: u64) {
+        self.dropped.insert(element, dropped);
+    }
+
+    pub fn dropped_frames(&self) -> u64 {
+        self.dropped.values().sum()
+    }
+
+    pub fn clear_dropped(&mut self) {
+        self.dropped.clear();
+    }
+
+    // bitrate in bits per second and frames per second, zero once the input has stalled
+    pub fn rates(&self) -> (u64, f64) {
+        match self.last_buffer {
+            Some(last) if last.elapsed().as_secs() < 3 => (self.bitrate, self.fps),
+            _ => (0, 0.0)
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct RtspStream {
+    pub id: String,
+    pub rtsp: String,
+    pub stream_type: String,
+    pub encode_options: EncodeOptions,
+    pub hls_options: Option<HLSOptions>,
+    pub video_bitrate: Option<u32>,
+    pub state: StreamState,
     pub status: bool,
     pub message: String,
     pub pipeline: Option<gstreamer::Pipeline>,
+    pub started_at: Option<Instant>,
+    pub reconnects: u32,
+    pub generation: u64,
+    pub stats: Arc<Mutex<StreamStats>>,
+    pub owner: String,
+    pub playlists: Arc<Mutex<HlsPlaylists>>,
+    pub paused: bool,
+    pub priority: i32,
+    pub name: Option<String>,
+    pub group: Option<String>,
+    pub labels: BTreeMap<String, String>,
+    pub on_demand: bool,
+    pub last_demand: Option<Instant>,
+    pub wake: Arc<tokio::sync::Notify>,
+    pub viewers: Arc<Mutex<ViewerRegistry>>,
+    pub max_viewers: Option<u32>,
+    pub webrtc_options: WebRtcOptions,
+}
+
+#[derive(Clone, FromRef)]
+pub struct AppState {
+    pub config: Arc<Config>,
+    pub streams: Arc<tokio::sync::Mutex<HashMap<String, RtspStream>>>,
+    pub metrics: Arc<Metrics>,
+    pub events: tokio::sync::broadcast::Sender<StreamEvent>,
+    pub webhooks: Arc<Webhooks>,
+    pub signalling: Arc<SignallingGate>,
+    pub limiter: Arc<RateLimiter>,
+    pub scheduler: Arc<Scheduler>,
+    pub hls: Arc<HlsAccounting>,
+}
+
+#[derive(Clone, Serialize, Debug, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StreamEventKind {
+    StateChanged { state: StreamState, message: String },
+    Error { message: String },
+    Warning { message: String },
+    Reconnecting { attempt: u32, delay: u64 },
+    Deleted
+}
+
+impl StreamEventKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            StreamEventKind::StateChanged { .. } => "state_changed",
+            StreamEventKind::Error { .. } => "error",
+            StreamEventKind::Warning { .. } => "warning",
+            StreamEventKind::Reconnecting { .. } => "reconnecting",
+            StreamEventKind::Deleted => "deleted",
+        }
+    }
 }
 
-#[derive(Clone, Serialize, Debug)]
+#[derive(Clone, Serialize, Debug, ToSchema)]
+pub struct StreamEvent {
+    pub stream: String,
+    pub timestamp: u64,
+    #[serde(flatten)]
+    pub kind: StreamEventKind
+}
+
+impl StreamEvent {
+    pub fn new(stream: &str, kind: StreamEventKind) -> Self {
+        StreamEvent {
+            stream: stream.to_string(),
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+            kind
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Debug, ToSchema)]
 pub struct RtspResponse{
+    pub id: String,
+    pub name: Option<String>,
+    pub group: Option<String>,
+    pub labels: BTreeMap<String, String>,
+    pub state: StreamState,
     pub status: bool,
-    pub message: String
-}
\ No newline at end of file
+    pub message: String,
+    pub queue_position: Option<usize>
+}
+
+#[derive(Clone, Serialize, Debug, ToSchema)]
+pub struct StreamInfo {
+    pub id: String,
+    pub name: Option<String>,
+    pub group: Option<String>,
+    pub labels: BTreeMap<String, String>,
+    pub source: String,
+    pub stream_type: String,
+    pub encode_options: EncodeOptions,
+    pub hls_options: Option<HLSOptions>,
+    pub video_bitrate: Option<u32>,
+    pub state: StreamState,
+    pub message: String,
+    pub input_caps: Option<String>,
+    pub output_caps: Vec<String>,
+    pub uptime: Option<u64>,
+    pub reconnects: u32,
+    pub bitrate: u64,
+    pub fps: f64,
+    // RTP packets lost or late in the jitterbuffer
+    pub lost_packets: u64,
+    // frames the decoders, encoders and sinks dropped, from their QoS messages
+    pub dropped_frames: u64,
+    pub hls_segments: Option<usize>,
+    pub queue_position: Option<usize>,
+    pub priority: i32,
+    pub on_demand: bool,
+    pub viewers: usize,
+    pub max_viewers: Option<u32>,
+    pub hls_viewers: usize,
+    pub webrtc_options: WebRtcOptions
+}
+#[derive(Deserialize, ToSchema)]
+pub struct ProbeData {
+    pub rtsp: String,
+    pub timeout: Option<u64>
+}
+
+#[derive(Clone, Serialize, Debug, ToSchema)]
+pub struct ProbeVideo {
+    pub codec: String,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub framerate: Option<f64>,
+    pub profile: Option<String>,
+    pub level: Option<String>,
+    pub caps: String
+}
+
+#[derive(Clone, Serialize, Debug, ToSchema)]
+pub struct ProbeAudio {
+    pub encoding: String,
+    pub clock_rate: Option<i32>,
+    pub channels: Option<String>,
+    pub caps: String
+}
+
+#[derive(Clone, Serialize, Debug, ToSchema)]
+pub struct ProbeResponse {
+    pub reachable: bool,
+    pub authorized: bool,
+    pub video: Option<ProbeVideo>,
+    pub audio: Vec<ProbeAudio>
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct WebhookData {
+    pub url: String,
+    pub secret: String,
+    pub offline_after: Option<u64>,
+    pub streams: Option<Vec<String>>,
+    pub events: Option<Vec<String>>
+}
+
+#[derive(Clone, Serialize, Debug, ToSchema)]
+pub struct Webhook {
+    pub id: String,
+    pub url: String,
+    #[serde(skip_serializing)]
+    pub secret: String,
+    pub offline_after: u64,
+    pub streams: Option<Vec<String>>,
+    pub events: Vec<String>
+}
+
+#[derive(Clone, Serialize, Debug, ToSchema)]
+pub struct WebhookDelivery {
+    pub id: String,
+    pub webhook: String,
+    pub event: String,
+    pub stream: String,
+    pub attempt: u32,
+    pub status: Option<u16>,
+    pub error: Option<String>,
+    pub success: bool,
+    pub timestamp: u64
+}
@@ -0,0 +1,120 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use axum_server::tls_rustls::RustlsConfig;
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConfig};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::TlsAcceptor;
+use crate::config::TlsConfig;
+
+fn load_certs(path: &str) -> Result<Vec<CertificateDer<'static>>, String> {
+    let file = File::open(path).map_err(|e| format!("Failed to open {}: {}", path, e))?;
+
+    rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Failed to read certificates from {}: {}", path, e))
+}
+
+fn load_key(path: &str) -> Result<PrivateKeyDer<'static>, String> {
+    let file = File::open(path).map_err(|e| format!("Failed to open {}: {}", path, e))?;
+
+    match rustls_pemfile::private_key(&mut BufReader::new(file)) {
+        Ok(Some(key)) => Ok(key),
+        Ok(None) => Err(format!("No private key found in {}", path)),
+        Err(e) => Err(format!("Failed to read private key from {}: {}", path, e)),
+    }
+}
+
+pub fn server_config(tls: &TlsConfig, client_ca: Option<&str>) -> Result<ServerConfig, String> {
+    let certs = load_certs(&tls.cert)?;
+    let key = load_key(&tls.key)?;
+
+    let builder = ServerConfig::builder();
+
+    let builder = match client_ca {
+        Some(ca) => {
+            let mut roots = RootCertStore::empty();
+
+            for cert in load_certs(ca)? {
+                roots.add(cert).map_err(|e| format!("Invalid client CA certificate: {}", e))?;
+            }
+
+            let verifier = WebPkiClientVerifier::builder(Arc::new(roots))
+                .build()
+                .map_err(|e| format!("Failed to build client verifier: {}", e))?;
+
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    let mut config = builder
+        .with_single_cert(certs, key)
+        .map_err(|e| format!("Invalid certificate or key: {}", e))?;
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+    Ok(config)
+}
+
+pub fn signalling_acceptor(tls: &TlsConfig) -> Result<TlsAcceptor, String> {
+    let mut config = server_config(tls, None)?;
+    // websocket upgrades need HTTP/1.1
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+// polls the certificate and key for changes and swaps the new pair into every TLS listener
+pub async fn watch_certificates(
+    tls: TlsConfig,
+    http: RustlsConfig,
+    admin: Option<RustlsConfig>,
+    signalling: Option<Arc<RwLock<TlsAcceptor>>>,
+) {
+    let mut last = (modified(&tls.cert), modified(&tls.key));
+
+    loop {
+        tokio::time::sleep(Duration::from_secs(tls.reload_interval.max(1))).await;
+
+        let current = (modified(&tls.cert), modified(&tls.key));
+
+        if current == last {
+            continue;
+        }
+
+        match server_config(&tls, None) {
+            Ok(config) => http.reload_from_config(Arc::new(config)),
+            Err(e) => {
+                eprintln!("Keeping previous certificate: {}", e);
+                continue;
+            }
+        }
+
+        if let Some(admin) = &admin {
+            match server_config(&tls, tls.client_ca.as_deref()) {
+                Ok(config) => admin.reload_from_config(Arc::new(config)),
+                Err(e) => eprintln!("Failed to reload admin certificate: {}", e),
+            }
+        }
+
+        if let Some(signalling) = &signalling {
+            match signalling_acceptor(&tls) {
+                Ok(acceptor) => {
+                    if let Ok(mut current) = signalling.write() {
+                        *current = acceptor;
+                    }
+                }
+                Err(e) => eprintln!("Failed to reload signalling certificate: {}", e),
+            }
+        }
+
+        println!("Reloaded TLS certificate from {}", tls.cert);
+        last = current;
+    }
+}
@@ -0,0 +1,112 @@
+use std::net::IpAddr;
+use axum::http::StatusCode;
+use axum::Json;
+use url::{Host, Url};
+use crate::config::SourcesConfig;
+use crate::structures::ResponseData;
+
+fn default_port(scheme: &str) -> u16 {
+    match scheme {
+        "rtsps" => 322,
+        "http" => 80,
+        "https" => 443,
+        _ => 554,
+    }
+}
+
+fn check_address(config: &SourcesConfig, host: &str, address: IpAddr) -> Result<(), String> {
+    // an IPv4-mapped IPv6 address must not slip past IPv4 rules
+    let address = match address {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(address),
+        IpAddr::V4(_) => address,
+    };
+
+    if let Some(net) = config.deny_cidrs.iter().find(|net| net.contains(&address)) {
+        return Err(format!("Host {} resolves to {}, which is in denied range {}", host, address, net));
+    }
+
+    if !config.allow_cidrs.is_empty() && !config.allow_cidrs.iter().any(|net| net.contains(&address)) {
+        return Err(format!("Host {} resolves to {}, which is not in an allowed range", host, address));
+    }
+
+    Ok(())
+}
+
+pub async fn validate_source(config: &SourcesConfig, rtsp: &str) -> Result<(), String> {
+    if rtsp.is_empty() {
+        return Err(String::from("Source URL is empty"));
+    }
+
+    if rtsp.len() > config.max_url_length {
+        return Err(format!("Source URL is {} bytes, longer than the maximum of {}", rtsp.len(), config.max_url_length));
+    }
+
+    // checked on the raw string, the URL parser silently strips tabs and newlines
+    if let Some(position) = rtsp.find(|c: char| c.is_control() || c.is_whitespace()) {
+        return Err(format!("Source URL contains a whitespace or control character at byte {}", position));
+    }
+
+    let url = Url::parse(rtsp).map_err(|e| format!("Source URL is not a valid URL: {}", e))?;
+
+    if !config.allowed_schemes.iter().any(|scheme| scheme.eq_ignore_ascii_case(url.scheme())) {
+        return Err(format!("Scheme '{}' is not allowed, expected one of: {}", url.scheme(), config.allowed_schemes.join(", ")));
+    }
+
+    let port = url.port().unwrap_or_else(|| default_port(url.scheme()));
+
+    let (host, addresses) = match url.host() {
+        Some(Host::Ipv4(ip)) => (ip.to_string(), vec![IpAddr::V4(ip)]),
+        Some(Host::Ipv6(ip)) => (ip.to_string(), vec![IpAddr::V6(ip)]),
+        Some(Host::Domain(domain)) => {
+            let addresses: Vec<IpAddr> = tokio::net::lookup_host((domain, port))
+                .await
+                .map_err(|e| format!("Failed to resolve host {}: {}", domain, e))?
+                .map(|address| address.ip())
+                .collect();
+
+            if addresses.is_empty() {
+                return Err(format!("Host {} did not resolve to any address", domain));
+            }
+
+            (domain.to_string(), addresses)
+        }
+        None => return Err(String::from("Source URL has no host")),
+    };
+
+    for address in addresses {
+        check_address(config, &host, address)?;
+    }
+
+    Ok(())
+}
+
+pub fn reject_source<T>(message: String) -> (StatusCode, Json<ResponseData<T>>) {
+    (StatusCode::UNPROCESSABLE_ENTITY, Json(ResponseData {
+        status: false,
+        message,
+        data: None
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn rejects_loopback_and_link_local_sources_by_default() {
+        let config = SourcesConfig::default();
+
+        for rtsp in ["rtsp://127.0.0.1/", "rtsp://127.0.1.1:8554/stream", "rtsp://[::1]/", "rtsp://[::ffff:127.0.0.1]/", "rtsp://169.254.169.254/", "rtsp://[fe80::1]/"] {
+            assert!(validate_source(&config, rtsp).await.is_err(), "{} was accepted", rtsp);
+        }
+    }
+
+    #[tokio::test]
+    async fn accepts_other_addresses_by_default() {
+        let config = SourcesConfig::default();
+
+        for rtsp in ["rtsp://192.168.1.20:554/stream", "rtsps://203.0.113.10/live", "rtsp://[2001:db8::10]/"] {
+            assert_eq!(validate_source(&config, rtsp).await, Ok(()));
+        }
+    }
+}
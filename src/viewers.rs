@@ -0,0 +1,280 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::Response;
+use gstreamer::prelude::{Cast, ObjectExt};
+use gstreamer_app::gst;
+use serde::Serialize;
+use utoipa::ToSchema;
+use crate::api::respond;
+use crate::openapi::ErrorResponse;
+use crate::signalling::SignallingGate;
+use crate::stream_manager::find_stream;
+use crate::streamer::{find_elements, walk_stats};
+use crate::structures::{AppState, ResponseData};
+
+#[derive(Debug)]
+struct ViewerEntry {
+    connected_at: u64,
+    bytes_sent: u64,
+    sampled_at: Option<Instant>,
+    bitrate: u64
+}
+
+// consumers of a stream's webrtcsink, kept up to date by its consumer-added/consumer-removed signals
+#[derive(Default, Debug)]
+pub struct ViewerRegistry {
+    viewers: HashMap<String, ViewerEntry>
+}
+
+impl ViewerRegistry {
+    pub fn clear(&mut self) {
+        self.viewers.clear();
+    }
+
+    pub fn count(&self) -> usize {
+        self.viewers.len()
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Viewer {
+    pub peer_id: String,
+    pub session_id: Option<String>,
+    /// unix timestamp
+    pub connected_at: u64,
+    pub codec: Option<String>,
+    /// bits per second sent to this viewer
+    pub bitrate: u64,
+    /// round trip time in seconds, as reported by the viewer
+    pub rtt: Option<f64>,
+    pub packets_lost: Option<i64>,
+    pub fraction_lost: Option<f64>
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+pub fn track_viewers(pipeline: &gst::Pipeline, registry: Arc<Mutex<ViewerRegistry>>) {
+    for sink in find_elements(pipeline.upcast_ref(), "webrtcsink") {
+        let added = registry.clone();
+        sink.connect("consumer-added", false, move |values| {
+            if let (Ok(peer_id), Ok(mut registry)) = (values[1].get::<String>(), added.lock()) {
+                registry.viewers.insert(peer_id, ViewerEntry { connected_at: now(), bytes_sent: 0, sampled_at: None, bitrate: 0 });
+            }
+            None
+        });
+
+        let removed = registry.clone();
+        sink.connect("consumer-removed", false, move |values| {
+            if let (Ok(peer_id), Ok(mut registry)) = (values[1].get::<String>(), removed.lock()) {
+                registry.viewers.remove(&peer_id);
+            }
+            None
+        });
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct ConsumerStats {
+    codec: Option<String>,
+    rtt: Option<f64>,
+    packets_lost: Option<i64>,
+    fraction_lost: Option<f64>,
+    bytes_sent: u64
+}
+
+// webrtcsink's stats hold one structure per session, keyed by the bare session id
+fn consumer_stats(stats: &gst::StructureRef, session_id: &str) -> Option<ConsumerStats> {
+    let consumer = stats.get::<gst::Structure>(session_id).ok()?;
+    let mut parsed = ConsumerStats::default();
+
+    walk_stats(&consumer, &mut |kind, s| {
+        if kind.contains("remote-inbound-rtp") {
+            parsed.rtt = s.get::<f64>("round-trip-time").ok().or(parsed.rtt);
+            parsed.packets_lost = s.get::<i64>("packets-lost").ok().or_else(|| s.get::<i32>("packets-lost").ok().map(i64::from)).or(parsed.packets_lost);
+            parsed.fraction_lost = s.get::<f64>("fraction-lost").ok().or(parsed.fraction_lost);
+        } else if kind.contains("outbound-rtp") && !kind.contains("remote") {
+            parsed.bytes_sent += s.get::<u64>("bytes-sent").unwrap_or(0);
+        } else if kind.contains("codec") {
+            parsed.codec = s.get::<String>("mime-type").ok().or(parsed.codec.take());
+        }
+    });
+
+    Some(parsed)
+}
+
+// RTP bytes sent across every session the sink reports, for pipelines ending in a webrtcsink
+pub fn webrtc_bytes_sent(pipeline: &gst::Pipeline) -> Option<u64> {
+    let sink = find_elements(pipeline.upcast_ref(), "webrtcsink").into_iter().next()?;
+    let stats = sink.property::<gst::Structure>("stats");
+
+    Some(stats.iter()
+        .filter_map(|(session_id, _)| consumer_stats(&stats, session_id.as_str()))
+        .map(|consumer| consumer.bytes_sent)
+        .sum())
+}
+
+// joins the registry with the sink's per-session stats; peers are mapped to their sessions through the signalling gate
+pub fn collect_viewers(stream_id: &str, pipeline: Option<&gst::Pipeline>, registry: &Mutex<ViewerRegistry>, gate: &SignallingGate) -> Vec<Viewer> {
+    let mut registry = match registry.lock() {
+        Ok(registry) => registry,
+        Err(_) => return vec![],
+    };
+
+    let sessions: HashMap<String, String> = gate.stream_sessions(stream_id)
+        .into_iter()
+        .map(|(session_id, peer_id)| (peer_id, session_id))
+        .collect();
+
+    let stats = pipeline
+        .and_then(|pipeline| find_elements(pipeline.upcast_ref(), "webrtcsink").into_iter().next())
+        .map(|sink| sink.property::<gst::Structure>("stats"));
+
+    let mut viewers: Vec<Viewer> = registry.viewers.iter_mut().map(|(peer_id, entry)| {
+        let session_id = sessions.get(peer_id).cloned();
+
+        let mut viewer = Viewer {
+            peer_id: peer_id.clone(),
+            session_id: session_id.clone(),
+            connected_at: entry.connected_at,
+            codec: None,
+            bitrate: entry.bitrate,
+            rtt: None,
+            packets_lost: None,
+            fraction_lost: None
+        };
+
+        let consumer = stats.as_ref().zip(session_id).and_then(|(stats, session_id)| consumer_stats(stats, &session_id));
+
+        if let Some(consumer) = consumer {
+            viewer.codec = consumer.codec;
+            viewer.rtt = consumer.rtt;
+            viewer.packets_lost = consumer.packets_lost;
+            viewer.fraction_lost = consumer.fraction_lost;
+
+            // the sink only reports totals, so the rate comes from the change since the last request
+            let sampled = Instant::now();
+            if let Some(previous) = entry.sampled_at {
+                let elapsed = sampled.duration_since(previous).as_secs_f64();
+
+                if elapsed >= 1.0 {
+                    entry.bitrate = (consumer.bytes_sent.saturating_sub(entry.bytes_sent) as f64 * 8.0 / elapsed) as u64;
+                    entry.bytes_sent = consumer.bytes_sent;
+                    entry.sampled_at = Some(sampled);
+                }
+            } else {
+                entry.bytes_sent = consumer.bytes_sent;
+                entry.sampled_at = Some(sampled);
+            }
+
+            viewer.bitrate = entry.bitrate;
+        }
+
+        viewer
+    }).collect();
+
+    viewers.sort_by_key(|viewer| viewer.connected_at);
+    viewers
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/streams/{id}/viewers",
+    tag = "streams",
+    params(("id" = String, Path, description = "Stream ID")),
+    responses(
+        (status = 200, description = "Connected WebRTC viewers", body = ResponseData<Vec<Viewer>>),
+        (status = 404, description = "Stream not found", body = ErrorResponse)
+    )
+)]
+pub async fn list_viewers(Path(id): Path<String>, State(state): State<AppState>) -> Response {
+    match find_stream(&state, &id).await {
+        Some(stream) => {
+            let viewers = collect_viewers(&stream.id, stream.pipeline.as_ref(), &stream.viewers, &state.signalling);
+            respond(StatusCode::OK, "Fetch Success", Some(viewers))
+        }
+        None => respond::<()>(StatusCode::NOT_FOUND, "Stream not found", None),
+    }
+}
+
+// ends the viewer's signalling session, the producer then tears down its peer connection
+#[utoipa::path(
+    delete,
+    path = "/api/v1/streams/{id}/viewers/{peer_id}",
+    tag = "streams",
+    params(
+        ("id" = String, Path, description = "Stream ID"),
+        ("peer_id" = String, Path, description = "Signalling peer ID of the viewer")
+    ),
+    responses(
+        (status = 200, description = "Viewer's session ended", body = ErrorResponse),
+        (status = 404, description = "Stream or viewer not found", body = ErrorResponse)
+    )
+)]
+pub async fn kick_viewer(Path((id, peer_id)): Path<(String, String)>, State(state): State<AppState>) -> Response {
+    if find_stream(&state, &id).await.is_none() {
+        return respond::<()>(StatusCode::NOT_FOUND, "Stream not found", None);
+    }
+
+    if state.signalling.end_sessions(&id, &peer_id) {
+        respond::<()>(StatusCode::OK, "Viewer disconnected", None)
+    } else {
+        respond::<()>(StatusCode::NOT_FOUND, "Viewer not found", None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SESSION: &str = "5f0c8e5e-2f4b-4c4e-9a51-3d7b1f0a9c21";
+
+    fn sink_stats() -> gst::Structure {
+        let session = gst::Structure::builder("application/x-webrtcsink-consumer-stats")
+            .field("remote-inbound-rtp_video", gst::Structure::builder("remote-inbound-rtp")
+                .field("round-trip-time", 0.042f64)
+                .field("packets-lost", 7i64)
+                .field("fraction-lost", 0.01f64)
+                .build())
+            .field("outbound-rtp_video", gst::Structure::builder("outbound-rtp")
+                .field("bytes-sent", 125_000u64)
+                .build())
+            .field("outbound-rtp_audio", gst::Structure::builder("outbound-rtp")
+                .field("bytes-sent", 5_000u64)
+                .build())
+            .field("codec_video", gst::Structure::builder("codec")
+                .field("mime-type", "video/H264")
+                .build())
+            .build();
+
+        gst::Structure::builder("application/x-webrtcsink-stats")
+            .field(SESSION, session)
+            .build()
+    }
+
+    #[test]
+    fn parses_consumer_stats_keyed_by_session_id() {
+        gst::init().unwrap();
+
+        let parsed = consumer_stats(&sink_stats(), SESSION).unwrap();
+
+        assert_eq!(parsed, ConsumerStats {
+            codec: Some(String::from("video/H264")),
+            rtt: Some(0.042),
+            packets_lost: Some(7),
+            fraction_lost: Some(0.01),
+            bytes_sent: 130_000
+        });
+    }
+
+    #[test]
+    fn unknown_session_has_no_stats() {
+        gst::init().unwrap();
+
+        assert!(consumer_stats(&sink_stats(), "consumer-peer").is_none());
+    }
+}
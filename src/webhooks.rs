@@ -0,0 +1,462 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use axum::extract::{Path, Query, State};
+use axum::Json;
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use tokio::sync::broadcast::error::RecvError;
+use utoipa::IntoParams;
+use crate::openapi::ErrorResponse;
+use crate::stream_manager::redact_rtsp;
+use crate::structures::{AppState, ResponseData, StreamEvent, StreamEventKind, StreamState, Webhook, WebhookData, WebhookDelivery};
+
+// default values
+const MAX_ATTEMPTS: u32 = 5;
+const RETRY_DELAY: u64 = 1;
+const DELIVERY_LOG_SIZE: usize = 500;
+const DELIVERY_TIMEOUT: u64 = 10;
+const DEFAULT_EVENTS: [&str; 2] = ["offline", "online"];
+
+static SEQUENCE: AtomicU64 = AtomicU64::new(0);
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+fn new_id() -> String {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+    let digest = Sha256::digest(format!("{}-{}", nanos, SEQUENCE.fetch_add(1, Ordering::SeqCst)).as_bytes());
+    hex::encode(&digest[..8])
+}
+
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(body);
+    hex::encode(mac.finalize().into_bytes())
+}
+
+pub struct Webhooks {
+    hooks: Mutex<HashMap<String, Webhook>>,
+    deliveries: Mutex<VecDeque<WebhookDelivery>>,
+    client: reqwest::Client,
+    // doubled after every failed attempt
+    retry_delay: Duration
+}
+
+impl Webhooks {
+    pub fn new() -> Self {
+        Self::with_timing(Duration::from_secs(RETRY_DELAY), Duration::from_secs(DELIVERY_TIMEOUT))
+    }
+
+    pub fn with_timing(retry_delay: Duration, timeout: Duration) -> Self {
+        Webhooks {
+            hooks: Mutex::new(HashMap::new()),
+            deliveries: Mutex::new(VecDeque::new()),
+            client: reqwest::Client::builder()
+                .timeout(timeout)
+                .build()
+                .unwrap_or_default(),
+            retry_delay
+        }
+    }
+
+    fn subscribers(&self, stream: &str, event: &str) -> Vec<Webhook> {
+        match self.hooks.lock() {
+            Ok(hooks) => hooks
+                .values()
+                .filter(|hook| hook.events.iter().any(|e| e == event))
+                .filter(|hook| hook.streams.as_ref().map(|ids| ids.iter().any(|id| id == stream)).unwrap_or(true))
+                .cloned()
+                .collect(),
+            Err(_) => vec![],
+        }
+    }
+
+    fn log(&self, delivery: WebhookDelivery) {
+        if let Ok(mut deliveries) = self.deliveries.lock() {
+            if deliveries.len() >= DELIVERY_LOG_SIZE {
+                deliveries.pop_front();
+            }
+            deliveries.push_back(delivery);
+        }
+    }
+
+    async fn deliver(&self, hook: Webhook, event: String, stream: String, data: serde_json::Value) {
+        let delivery_id = new_id();
+        let body = json!({
+            "id": delivery_id,
+            "event": format!("stream.{}", event),
+            "stream": stream,
+            "timestamp": now(),
+            "data": data,
+        }).to_string();
+
+        let signature = format!("sha256={}", sign(&hook.secret, body.as_bytes()));
+        let mut delay = self.retry_delay;
+
+        for attempt in 1..=MAX_ATTEMPTS {
+            let result = self.client
+                .post(&hook.url)
+                .header("Content-Type", "application/json")
+                .header("X-Webhook-Id", delivery_id.as_str())
+                .header("X-Webhook-Event", format!("stream.{}", event))
+                .header("X-Webhook-Signature", signature.as_str())
+                .body(body.clone())
+                .send()
+                .await;
+
+            let (status, error) = match result {
+                Ok(response) => (Some(response.status().as_u16()), None),
+                Err(e) => (None, Some(e.to_string())),
+            };
+            let success = status.map(|code| (200..300).contains(&code)).unwrap_or(false);
+
+            self.log(WebhookDelivery {
+                id: delivery_id.clone(),
+                webhook: hook.id.clone(),
+                event: event.clone(),
+                stream: stream.clone(),
+                attempt,
+                status,
+                error,
+                success,
+                timestamp: now()
+            });
+
+            if success {
+                return;
+            }
+
+            if attempt < MAX_ATTEMPTS {
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+        }
+    }
+}
+
+struct Outage {
+    since: Instant,
+    notified: HashSet<String>
+}
+
+fn dispatch(state: &AppState, hook: Webhook, event: &str, stream: &str, data: serde_json::Value) {
+    let webhooks = state.webhooks.clone();
+    let event = event.to_string();
+    let stream = stream.to_string();
+
+    tokio::spawn(async move {
+        webhooks.deliver(hook, event, stream, data).await;
+    });
+}
+
+async fn stream_details(state: &AppState, id: &str) -> serde_json::Value {
+    let streams_lock = state.streams.lock().await;
+
+    match streams_lock.values().find(|s| s.id == id) {
+        Some(stream) => json!({
+            "source": redact_rtsp(&stream.rtsp),
+            "stream_type": stream.stream_type,
+            "state": stream.state,
+            "message": stream.message,
+        }),
+        None => json!({}),
+    }
+}
+
+// forwards supervisor events to subscribers and raises offline/online once a stream has been down for the configured time
+pub async fn run_dispatcher(state: AppState) {
+    let mut receiver = state.events.subscribe();
+    let mut ticker = tokio::time::interval(Duration::from_secs(1));
+    let mut outages: HashMap<String, Outage> = HashMap::new();
+
+    loop {
+        tokio::select! {
+            received = receiver.recv() => {
+                let event = match received {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return,
+                };
+
+                handle_event(&state, &event, &mut outages).await;
+            }
+            _ = ticker.tick() => {
+                for (stream, outage) in outages.iter_mut() {
+                    for hook in state.webhooks.subscribers(stream, "offline") {
+                        if outage.notified.contains(&hook.id) || outage.since.elapsed().as_secs() < hook.offline_after {
+                            continue;
+                        }
+
+                        outage.notified.insert(hook.id.clone());
+
+                        let mut data = stream_details(&state, stream).await;
+                        data["offline_for"] = json!(outage.since.elapsed().as_secs());
+
+                        dispatch(&state, hook, "offline", stream, data);
+                    }
+                }
+            }
+        }
+    }
+}
+
+async fn handle_event(state: &AppState, event: &StreamEvent, outages: &mut HashMap<String, Outage>) {
+    let name = event.kind.name();
+
+    for hook in state.webhooks.subscribers(&event.stream, name) {
+        dispatch(state, hook, name, &event.stream, serde_json::to_value(&event.kind).unwrap_or_default());
+    }
+
+    match &event.kind {
+        StreamEventKind::StateChanged { state: StreamState::Running, .. } => {
+            if let Some(outage) = outages.remove(&event.stream) {
+                let data = json!({ "offline_for": outage.since.elapsed().as_secs() });
+
+                for hook in state.webhooks.subscribers(&event.stream, "online") {
+                    if outage.notified.contains(&hook.id) {
+                        dispatch(state, hook, "online", &event.stream, data.clone());
+                    }
+                }
+            }
+        }
+        StreamEventKind::StateChanged { state: StreamState::Starting | StreamState::Queued, .. } => {}
+        // a deliberate pause, or an on-demand stream nobody is watching, is not an outage
+        StreamEventKind::StateChanged { state: StreamState::Paused | StreamState::Idle, .. } => {
+            outages.remove(&event.stream);
+        }
+        StreamEventKind::StateChanged { .. } => {
+            outages.entry(event.stream.clone()).or_insert_with(|| Outage {
+                since: Instant::now(),
+                notified: HashSet::new()
+            });
+        }
+        StreamEventKind::Deleted => {
+            outages.remove(&event.stream);
+        }
+        _ => {}
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/webhooks",
+    tag = "webhooks",
+    request_body = WebhookData,
+    responses(
+        (status = 200, description = "Webhook registered", body = ResponseData<Webhook>)
+    )
+)]
+pub async fn add_webhook(State(state): State<AppState>, Json(payload): Json<WebhookData>) -> Json<ResponseData<Webhook>> {
+    if !payload.url.starts_with("http://") && !payload.url.starts_with("https://") {
+        return Json(ResponseData {
+            status: false,
+            message: String::from("Webhook url must be http or https"),
+            data: None
+        });
+    }
+
+    if payload.secret.is_empty() {
+        return Json(ResponseData {
+            status: false,
+            message: String::from("Webhook secret must not be empty"),
+            data: None
+        });
+    }
+
+    let hook = Webhook {
+        id: new_id(),
+        url: payload.url,
+        secret: payload.secret,
+        offline_after: payload.offline_after.unwrap_or(0),
+        streams: payload.streams,
+        events: payload.events.unwrap_or_else(|| DEFAULT_EVENTS.iter().map(|e| e.to_string()).collect())
+    };
+
+    if let Ok(mut hooks) = state.webhooks.hooks.lock() {
+        hooks.insert(hook.id.clone(), hook.clone());
+    }
+
+    Json(ResponseData {
+        status: true,
+        message: String::from("Webhook created"),
+        data: Some(hook)
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/webhooks",
+    tag = "webhooks",
+    responses(
+        (status = 200, description = "Registered webhooks", body = ResponseData<Vec<Webhook>>)
+    )
+)]
+pub async fn get_webhooks(State(state): State<AppState>) -> Json<ResponseData<Vec<Webhook>>> {
+    let hooks = state.webhooks.hooks.lock().map(|hooks| hooks.values().cloned().collect()).unwrap_or_default();
+
+    Json(ResponseData {
+        status: true,
+        message: String::from("Fetch Success"),
+        data: Some(hooks)
+    })
+}
+
+#[utoipa::path(
+    delete,
+    path = "/webhooks/{id}",
+    tag = "webhooks",
+    params(("id" = String, Path, description = "Webhook ID")),
+    responses(
+        (status = 200, description = "Webhook deleted, `status` is false if not found", body = ErrorResponse)
+    )
+)]
+pub async fn delete_webhook(Path(id): Path<String>, State(state): State<AppState>) -> Json<ResponseData> {
+    let removed = state.webhooks.hooks.lock().map(|mut hooks| hooks.remove(&id).is_some()).unwrap_or(false);
+
+    Json(ResponseData {
+        status: removed,
+        message: if removed { String::from("Webhook deleted") } else { String::from("Webhook not found") },
+        data: None
+    })
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DeliveriesQuery {
+    pub webhook: Option<String>
+}
+
+#[utoipa::path(
+    get,
+    path = "/webhooks/deliveries",
+    tag = "webhooks",
+    params(DeliveriesQuery),
+    responses(
+        (status = 200, description = "Recent delivery attempts", body = ResponseData<Vec<WebhookDelivery>>)
+    )
+)]
+pub async fn get_deliveries(Query(query): Query<DeliveriesQuery>, State(state): State<AppState>) -> Json<ResponseData<Vec<WebhookDelivery>>> {
+    let deliveries = state.webhooks.deliveries.lock()
+        .map(|deliveries| deliveries
+            .iter()
+            .filter(|d| query.webhook.as_ref().map(|id| id == &d.webhook).unwrap_or(true))
+            .cloned()
+            .collect())
+        .unwrap_or_default();
+
+    Json(ResponseData {
+        status: true,
+        message: String::from("Fetch Success"),
+        data: Some(deliveries)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::routing::post;
+    use axum::Router;
+    use super::*;
+
+    struct Received {
+        at: Instant,
+        signature: String,
+        event: String,
+        body: String
+    }
+
+    // answers 500 to the first `failures` requests, then 200
+    async fn receiver(failures: usize) -> (String, Arc<Mutex<Vec<Received>>>) {
+        let received = Arc::new(Mutex::new(vec![]));
+        let log = received.clone();
+
+        let app = Router::new().route("/hook", post(move |headers: HeaderMap, body: String| {
+            let log = log.clone();
+
+            async move {
+                let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok()).unwrap_or_default().to_string();
+                let mut log = log.lock().unwrap();
+
+                log.push(Received { at: Instant::now(), signature: header("X-Webhook-Signature"), event: header("X-Webhook-Event"), body });
+
+                if log.len() <= failures { StatusCode::INTERNAL_SERVER_ERROR } else { StatusCode::OK }
+            }
+        }));
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        (url, received)
+    }
+
+    const TEST_RETRY_DELAY: Duration = Duration::from_millis(50);
+
+    fn webhooks() -> Webhooks {
+        Webhooks::with_timing(TEST_RETRY_DELAY, Duration::from_secs(5))
+    }
+
+    fn hook(url: String) -> Webhook {
+        Webhook {
+            id: String::from("hook-1"),
+            url,
+            secret: String::from("s3cret"),
+            offline_after: 0,
+            streams: None,
+            events: vec![String::from("offline")]
+        }
+    }
+
+    #[tokio::test]
+    async fn signs_retries_with_backoff_and_logs_every_attempt() {
+        let (url, received) = receiver(2).await;
+        let webhooks = webhooks();
+
+        webhooks.deliver(hook(url), String::from("offline"), String::from("cam-1"), json!({ "offline_for": 30 })).await;
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 3);
+
+        for request in received.iter() {
+            assert_eq!(request.signature, format!("sha256={}", sign("s3cret", request.body.as_bytes())));
+            assert_eq!(request.event, "stream.offline");
+        }
+
+        // every retry carries the same delivery
+        let body: serde_json::Value = serde_json::from_str(&received[0].body).unwrap();
+        assert_eq!(body["event"], "stream.offline");
+        assert_eq!(body["stream"], "cam-1");
+        assert_eq!(body["data"]["offline_for"], 30);
+        assert!(received.iter().all(|request| request.body == received[0].body));
+
+        assert!(received[1].at.duration_since(received[0].at) >= TEST_RETRY_DELAY);
+        assert!(received[2].at.duration_since(received[1].at) >= TEST_RETRY_DELAY * 2);
+
+        let deliveries = webhooks.deliveries.lock().unwrap();
+        let attempts: Vec<(u32, Option<u16>, bool)> = deliveries.iter().map(|d| (d.attempt, d.status, d.success)).collect();
+        assert_eq!(attempts, vec![(1, Some(500), false), (2, Some(500), false), (3, Some(200), true)]);
+        assert!(deliveries.iter().all(|d| d.webhook == "hook-1" && d.id == body["id"] && d.stream == "cam-1"));
+    }
+
+    #[tokio::test]
+    async fn logs_connection_errors() {
+        // nothing listens on the port once the listener is dropped
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        drop(listener);
+
+        let webhooks = webhooks();
+        webhooks.deliver(hook(url), String::from("offline"), String::from("cam-1"), json!({})).await;
+
+        let deliveries = webhooks.deliveries.lock().unwrap();
+        let attempts: Vec<u32> = deliveries.iter().map(|d| d.attempt).collect();
+        assert_eq!(attempts, (1..=MAX_ATTEMPTS).collect::<Vec<u32>>());
+        assert!(deliveries.iter().all(|d| d.status.is_none() && d.error.is_some() && !d.success));
+    }
+}
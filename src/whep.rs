@@ -0,0 +1,430 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use axum::extract::{Path, State};
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use futures::channel::mpsc::UnboundedReceiver;
+use futures::StreamExt;
+use serde_json::{json, Value};
+use crate::demand::touch_stream;
+use crate::ice::browser_servers;
+use crate::signalling::SignallingGate;
+use crate::structures::{AppState, IceOptions};
+
+// default values
+const PRODUCER_POLL: u64 = 200;
+const NEGOTIATION_TIMEOUT: u64 = 10;
+// candidates are gathered into the answer until none arrive for CANDIDATE_QUIET, or for at most CANDIDATE_WAIT
+const CANDIDATE_QUIET: u64 = 500;
+const CANDIDATE_WAIT: u64 = 3000;
+
+fn reject(status: StatusCode, message: impl Into<String>) -> Response {
+    (status, message.into()).into_response()
+}
+
+fn is_content_type(headers: &HeaderMap, expected: &str) -> bool {
+    headers.get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.split(';').next().unwrap_or_default().trim().eq_ignore_ascii_case(expected))
+        .unwrap_or(false)
+}
+
+// media section IDs in the order of their m= lines
+fn offer_mids(sdp: &str) -> Vec<String> {
+    sdp.lines()
+        .filter_map(|line| line.trim().strip_prefix("a=mid:"))
+        .map(|mid| mid.to_string())
+        .collect()
+}
+
+fn close_section(out: &mut String, section: Option<u64>, candidates: &[(u64, String)]) {
+    if let Some(index) = section {
+        for (_, candidate) in candidates.iter().filter(|(i, _)| *i == index) {
+            out.push_str(&format!("a={}\r\n", candidate.trim_start_matches("a=")));
+        }
+        out.push_str("a=end-of-candidates\r\n");
+    }
+}
+
+// WHEP answers can't be trickled, so the producer's candidates are written into their media sections
+fn with_candidates(sdp: &str, candidates: &[(u64, String)]) -> String {
+    let mut out = String::with_capacity(sdp.len());
+    let mut section: Option<u64> = None;
+
+    for line in sdp.lines() {
+        let line = line.trim_end_matches('\r');
+
+        if line.is_empty() {
+            continue;
+        }
+
+        if line.starts_with("m=") {
+            close_section(&mut out, section, candidates);
+            section = Some(section.map(|index| index + 1).unwrap_or(0));
+        }
+
+        out.push_str(line);
+        out.push_str("\r\n");
+    }
+
+    close_section(&mut out, section, candidates);
+    out
+}
+
+// `Link` headers advertising the stream's ICE servers, as WHEP clients expect them
+fn ice_links(id: &str, ice: &IceOptions) -> Vec<HeaderValue> {
+    browser_servers(ice, id).ice_servers
+        .into_iter()
+        .flat_map(|server| {
+            let credentials = match (&server.username, &server.credential) {
+                (Some(username), Some(credential)) => format!("; username=\"{}\"; credential=\"{}\"; credential-type=\"password\"", username, credential),
+                _ => String::new(),
+            };
+
+            server.urls.into_iter().map(move |url| format!("<{}>; rel=\"ice-server\"{}", url, credentials))
+        })
+        .filter_map(|link| HeaderValue::from_str(&link).ok())
+        .collect()
+}
+
+async fn wait_for_producer(state: &AppState, id: &str) -> Option<String> {
+    let deadline = Instant::now() + Duration::from_secs(state.config.on_demand.start_wait);
+
+    loop {
+        if let Some(producer) = state.signalling.stream_producer(id) {
+            return Some(producer);
+        }
+
+        if Instant::now() >= deadline {
+            return None;
+        }
+
+        tokio::time::sleep(Duration::from_millis(PRODUCER_POLL)).await;
+    }
+}
+
+fn session_id(event: &Value) -> Option<&str> {
+    event.get("sessionId").and_then(Value::as_str)
+}
+
+// waits for the session ID and the producer's answer, collecting candidates on the way
+async fn negotiate(events: &mut UnboundedReceiver<Value>) -> Result<(String, String, Vec<(u64, String)>), Response> {
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(NEGOTIATION_TIMEOUT);
+    let mut session = None;
+    let mut answer = None;
+    let mut candidates = vec![];
+
+    while answer.is_none() {
+        let event = match tokio::time::timeout_at(deadline, events.next()).await {
+            Ok(Some(event)) => event,
+            Ok(None) => return Err(reject(StatusCode::BAD_GATEWAY, "Signalling session closed")),
+            Err(_) => return Err(reject(StatusCode::GATEWAY_TIMEOUT, "Producer did not answer")),
+        };
+
+        match event.get("type").and_then(Value::as_str) {
+            Some("sessionStarted") => session = session_id(&event).map(|id| id.to_string()),
+            Some("peer") => {
+                if let Some(sdp) = event.get("sdp") {
+                    if sdp.get("type").and_then(Value::as_str) != Some("answer") {
+                        return Err(reject(StatusCode::NOT_IMPLEMENTED, "Producer does not accept offers from viewers"));
+                    }
+                    answer = sdp.get("sdp").and_then(Value::as_str).map(|sdp| sdp.to_string());
+                } else if let Some(ice) = event.get("ice") {
+                    if let (Some(candidate), Some(index)) = (ice.get("candidate").and_then(Value::as_str), ice.get("sdpMLineIndex").and_then(Value::as_u64)) {
+                        candidates.push((index, candidate.to_string()));
+                    }
+                }
+            }
+            Some("error") => {
+                let details = event.get("details").and_then(Value::as_str).unwrap_or("Session refused");
+                return Err(reject(StatusCode::SERVICE_UNAVAILABLE, details));
+            }
+            Some("endSession") => return Err(reject(StatusCode::BAD_GATEWAY, "Producer ended the session")),
+            _ => {}
+        }
+    }
+
+    let session = session.ok_or_else(|| reject(StatusCode::BAD_GATEWAY, "Signalling session was not started"))?;
+    let answer = answer.unwrap_or_default();
+
+    let gather_deadline = tokio::time::Instant::now() + Duration::from_millis(CANDIDATE_WAIT);
+
+    loop {
+        let quiet = (tokio::time::Instant::now() + Duration::from_millis(CANDIDATE_QUIET)).min(gather_deadline);
+
+        let event = match tokio::time::timeout_at(quiet, events.next()).await {
+            Ok(Some(event)) => event,
+            _ => break,
+        };
+
+        if let Some(ice) = event.get("ice") {
+            if let (Some(candidate), Some(index)) = (ice.get("candidate").and_then(Value::as_str), ice.get("sdpMLineIndex").and_then(Value::as_u64)) {
+                candidates.push((index, candidate.to_string()));
+            }
+        }
+    }
+
+    Ok((session, answer, candidates))
+}
+
+// hangs the relay up once the producer or signalling ends the session
+async fn watch_session(gate: Arc<SignallingGate>, peer_id: String, mut events: UnboundedReceiver<Value>) {
+    while let Some(event) = events.next().await {
+        if event.get("type").and_then(Value::as_str) == Some("endSession") {
+            break;
+        }
+    }
+
+    gate.close_relay(&peer_id);
+}
+
+// an on-demand stream is started first, and the offer waits for it like a signalling viewer would
+#[utoipa::path(
+    post,
+    path = "/whep/{id}",
+    tag = "playback",
+    params(("id" = String, Path, description = "Stream ID")),
+    request_body(content = String, content_type = "application/sdp", description = "The viewer's SDP offer"),
+    responses(
+        (status = 201, description = "SDP answer, with the session resource in `Location` and ICE servers in `Link`", content_type = "application/sdp", body = String),
+        (status = 404, description = "Stream not found"),
+        (status = 415, description = "Body is not application/sdp"),
+        (status = 503, description = "Stream did not start in time, or refused the viewer")
+    )
+)]
+pub async fn whep_offer(Path(id): Path<String>, State(state): State<AppState>, headers: HeaderMap, offer: String) -> Response {
+    if !is_content_type(&headers, "application/sdp") {
+        return reject(StatusCode::UNSUPPORTED_MEDIA_TYPE, "Expected an application/sdp offer");
+    }
+
+    let streams_lock = state.streams.lock().await;
+    let ice = streams_lock.values()
+        .find(|s| s.id == id && s.stream_type != "HLS")
+        .map(|s| s.webrtc_options.ice.clone().unwrap_or_default().merged(&state.config.ice));
+    drop(streams_lock);
+
+    let ice = match ice {
+        Some(ice) => ice,
+        None => return reject(StatusCode::NOT_FOUND, "Stream not found"),
+    };
+
+    touch_stream(&state, &id).await;
+
+    let producer = match wait_for_producer(&state, &id).await {
+        Some(producer) => producer,
+        None => return (StatusCode::SERVICE_UNAVAILABLE, [(header::RETRY_AFTER, "2")], "Stream is starting").into_response(),
+    };
+
+    let (peer_id, mut events) = match state.signalling.open_relay(&id, offer_mids(&offer)) {
+        Some(relay) => relay,
+        None => return reject(StatusCode::INTERNAL_SERVER_ERROR, "Failed to join signalling"),
+    };
+
+    if !state.signalling.relay(&peer_id, json!({ "type": "startSession", "peerId": producer, "offer": offer })) {
+        let refused = negotiate(&mut events).await.err();
+        state.signalling.close_relay(&peer_id);
+        return refused.unwrap_or_else(|| reject(StatusCode::SERVICE_UNAVAILABLE, "Session refused"));
+    }
+
+    let (session, answer, candidates) = match negotiate(&mut events).await {
+        Ok(negotiated) => negotiated,
+        Err(response) => {
+            state.signalling.close_relay(&peer_id);
+            return response;
+        }
+    };
+
+    tokio::spawn(watch_session(state.signalling.clone(), peer_id, events));
+
+    let mut response = (StatusCode::CREATED, [(header::CONTENT_TYPE, "application/sdp")], with_candidates(&answer, &candidates)).into_response();
+
+    if let Ok(location) = HeaderValue::from_str(&format!("/whep/{}/{}", id, session)) {
+        response.headers_mut().insert(header::LOCATION, location);
+    }
+
+    for link in ice_links(&id, &ice) {
+        response.headers_mut().append(header::LINK, link);
+    }
+
+    response
+}
+
+#[utoipa::path(
+    patch,
+    path = "/whep/{id}/{session}",
+    tag = "playback",
+    params(
+        ("id" = String, Path, description = "Stream ID"),
+        ("session" = String, Path, description = "Session from the offer's `Location`")
+    ),
+    request_body(content = String, content_type = "application/trickle-ice-sdpfrag", description = "The viewer's new ICE candidates"),
+    responses(
+        (status = 204, description = "Candidates passed to the producer"),
+        (status = 404, description = "Session not found"),
+        (status = 415, description = "Body is not application/trickle-ice-sdpfrag")
+    )
+)]
+pub async fn whep_candidates(Path((id, session)): Path<(String, String)>, State(state): State<AppState>, headers: HeaderMap, fragment: String) -> Response {
+    if !is_content_type(&headers, "application/trickle-ice-sdpfrag") {
+        return reject(StatusCode::UNSUPPORTED_MEDIA_TYPE, "Expected an application/trickle-ice-sdpfrag body");
+    }
+
+    let (peer_id, mids) = match state.signalling.relay_session(&id, &session) {
+        Some(relay) => relay,
+        None => return reject(StatusCode::NOT_FOUND, "Session not found"),
+    };
+
+    let mut index = None;
+
+    for line in fragment.lines().map(str::trim) {
+        if let Some(mid) = line.strip_prefix("a=mid:") {
+            index = mids.iter().position(|m| m == mid);
+        } else if let (Some(candidate), Some(index)) = (line.strip_prefix("a="), index) {
+            if candidate.starts_with("candidate:") {
+                state.signalling.relay(&peer_id, json!({
+                    "type": "peer",
+                    "sessionId": session,
+                    "ice": { "candidate": candidate, "sdpMLineIndex": index }
+                }));
+            }
+        }
+    }
+
+    StatusCode::NO_CONTENT.into_response()
+}
+
+#[utoipa::path(
+    delete,
+    path = "/whep/{id}/{session}",
+    tag = "playback",
+    params(
+        ("id" = String, Path, description = "Stream ID"),
+        ("session" = String, Path, description = "Session from the offer's `Location`")
+    ),
+    responses(
+        (status = 200, description = "Session ended"),
+        (status = 404, description = "Session not found")
+    )
+)]
+pub async fn whep_delete(Path((id, session)): Path<(String, String)>, State(state): State<AppState>) -> Response {
+    match state.signalling.relay_session(&id, &session) {
+        Some((peer_id, _)) => {
+            state.signalling.close_relay(&peer_id);
+            StatusCode::OK.into_response()
+        }
+        None => reject(StatusCode::NOT_FOUND, "Session not found"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap};
+    use std::sync::Mutex;
+    use axum::body::to_bytes;
+    use tokio::sync::{broadcast, Notify};
+    use crate::accounting::HlsAccounting;
+    use crate::config::Config;
+    use crate::limits::RateLimiter;
+    use crate::metrics::Metrics;
+    use crate::playlist::HlsPlaylists;
+    use crate::scheduler::Scheduler;
+    use crate::signalling::spawn_server;
+    use crate::structures::{EncodeOptions, RtspStream, StreamState, StreamStats, WebRtcOptions};
+    use crate::viewers::ViewerRegistry;
+    use crate::webhooks::Webhooks;
+    use super::*;
+
+    const OFFER: &str = "v=0\r\no=- 1 1 IN IP4 0.0.0.0\r\ns=-\r\nt=0 0\r\nm=video 9 UDP/TLS/RTP/SAVPF 96\r\na=mid:video0\r\na=recvonly\r\n";
+    const ANSWER: &str = "v=0\r\no=- 2 1 IN IP4 0.0.0.0\r\ns=-\r\nt=0 0\r\nm=video 9 UDP/TLS/RTP/SAVPF 96\r\na=mid:video0\r\na=sendonly\r\n";
+    const CANDIDATE: &str = "candidate:1 1 UDP 2122252543 192.0.2.1 50000 typ host";
+
+    fn stream(id: &str) -> RtspStream {
+        RtspStream {
+            id: id.to_string(),
+            rtsp: String::from("rtsp://camera.test/stream"),
+            stream_type: String::from("WEBRTC"),
+            encode_options: EncodeOptions::NONE,
+            hls_options: None,
+            video_bitrate: None,
+            state: StreamState::Running,
+            status: true,
+            message: String::from("Started"),
+            pipeline: None,
+            started_at: Some(std::time::Instant::now()),
+            reconnects: 0,
+            generation: 0,
+            stats: Arc::new(Mutex::new(StreamStats::default())),
+            owner: String::from("test"),
+            playlists: Arc::new(Mutex::new(HlsPlaylists::new(5, 2))),
+            paused: false,
+            priority: 0,
+            name: None,
+            group: None,
+            labels: BTreeMap::new(),
+            on_demand: false,
+            last_demand: None,
+            wake: Arc::new(Notify::new()),
+            viewers: Arc::new(Mutex::new(ViewerRegistry::default())),
+            max_viewers: None,
+            webrtc_options: WebRtcOptions::default(),
+        }
+    }
+
+    // goes through the real signalling handler, so the offer has to survive the pinned protocol's messages to reach the producer
+    #[tokio::test]
+    async fn offer_is_answered_by_the_producer() {
+        let (_server, signalling, _demands) = spawn_server(None);
+        let (events, _) = broadcast::channel(16);
+        let config = Config::default();
+
+        let state = AppState {
+            scheduler: Arc::new(Scheduler::new(&config.startup)),
+            config: Arc::new(config),
+            streams: Arc::new(tokio::sync::Mutex::new(HashMap::from([(String::from("rtsp://camera.test/stream"), stream("cam"))]))),
+            metrics: Arc::new(Metrics::new()),
+            events,
+            webhooks: Arc::new(Webhooks::new()),
+            signalling: signalling.clone(),
+            limiter: Arc::new(RateLimiter::new()),
+            hls: Arc::new(HlsAccounting::new()),
+        };
+
+        // a producer joined in-process, answering like webrtcsink does when a consumer brings its own offer
+        let (producer, mut producer_events) = signalling.open_relay("cam", vec![]).unwrap();
+        assert!(signalling.relay(&producer, json!({ "type": "setPeerStatus", "roles": ["producer"], "meta": { "stream-id": "cam" } })));
+
+        let answering = signalling.clone();
+        let offered = tokio::spawn(async move {
+            while let Some(event) = producer_events.next().await {
+                if event.get("type").and_then(Value::as_str) != Some("startSession") {
+                    continue;
+                }
+
+                let session = event.get("sessionId").and_then(Value::as_str).unwrap_or_default().to_string();
+                answering.relay(&producer, json!({ "type": "peer", "sessionId": session, "sdp": { "type": "answer", "sdp": ANSWER } }));
+                answering.relay(&producer, json!({ "type": "peer", "sessionId": session, "ice": { "candidate": CANDIDATE, "sdpMLineIndex": 0 } }));
+
+                return event.get("offer").and_then(Value::as_str).map(|offer| offer.to_string());
+            }
+
+            None
+        });
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("application/sdp"));
+
+        let response = whep_offer(Path(String::from("cam")), State(state), headers, OFFER.to_string()).await;
+
+        assert_eq!(offered.await.unwrap().as_deref(), Some(OFFER));
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let location = response.headers().get(header::LOCATION).and_then(|v| v.to_str().ok()).unwrap_or_default().to_string();
+        assert!(location.starts_with("/whep/cam/"), "{}", location);
+
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = String::from_utf8_lossy(&body);
+
+        assert!(body.contains("a=sendonly\r\n"), "{}", body);
+        assert!(body.contains(&format!("a={}\r\na=end-of-candidates\r\n", CANDIDATE)), "{}", body);
+    }
+}